@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{Mutex as AsyncMutex, Notify};
+
+use crate::AppError;
+
+/**
+ * Tracks in-flight queries by the id the frontend assigned them. Each adapter stores whatever it
+ * needs to stop a query server-side as the handle, e.g. the MySQL connection id.
+ */
+#[derive(Clone)]
+pub struct RunningQueries<H: Clone> {
+    queries: Arc<Mutex<HashMap<String, RunningQuery<H>>>>,
+}
+
+#[derive(Clone)]
+struct RunningQuery<H: Clone> {
+    handle: H,
+    cancelled: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+    /// held while a kill is on its way to the server
+    killing: Arc<AsyncMutex<()>>,
+}
+
+impl<H: Clone> Default for RunningQueries<H> {
+    fn default() -> Self {
+        RunningQueries {
+            queries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<H: Clone> RunningQueries<H> {
+    /**
+     * Registers a query. It stays cancellable until the returned guard is dropped.
+     * Queries without an id can't be cancelled, but still get a guard.
+     */
+    pub fn start(&self, query_id: Option<String>, handle: H) -> QueryGuard<H> {
+        let running = RunningQuery {
+            handle: handle,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            notify: Arc::new(Notify::new()),
+            killing: Arc::new(AsyncMutex::new(())),
+        };
+        let guard = QueryGuard {
+            queries: self.queries.clone(),
+            query_id: query_id.clone(),
+            cancelled: running.cancelled.clone(),
+            notify: running.notify.clone(),
+            killing: running.killing.clone(),
+        };
+        if let (Some(query_id), Ok(mut queries)) = (query_id, self.queries.lock()) {
+            queries.insert(query_id, running);
+        }
+        guard
+    }
+
+    /**
+     * Flags the query as cancelled and returns its handle, or None if it isn't running (anymore).
+     */
+    pub fn cancel(&self, query_id: &str) -> Option<H> {
+        self.cancel_with(query_id, H::clone)
+    }
+
+    /**
+     * Like cancel, but stops the query through its handle before the registry is unlocked. The
+     * query can't end meanwhile, as dropping its guard takes the same lock, so the handle can't
+     * outlive it.
+     */
+    pub fn cancel_with<R>(&self, query_id: &str, stop: impl FnOnce(&H) -> R) -> Option<R> {
+        let queries = self.queries.lock().ok()?;
+        let running = queries.get(query_id)?;
+        running.cancelled.store(true, Ordering::SeqCst);
+        running.notify.notify_one();
+        Some(stop(&running.handle))
+    }
+
+    /**
+     * Like cancel_with, for drivers that stop a query from another connection, e.g. with KILL
+     * QUERY. The query's connection can only be released through QueryGuard::finish, which waits
+     * for the kill to be sent, so it can't hit whatever the connection runs next.
+     * A kill already on its way does the job, so it isn't sent twice.
+     */
+    pub async fn kill_with<R, F: Future<Output = R>>(
+        &self,
+        query_id: &str,
+        kill: impl FnOnce(H) -> F,
+    ) -> Option<R> {
+        let (handle, killing) = {
+            let queries = self.queries.lock().ok()?;
+            let running = queries.get(query_id)?;
+            let killing = running.killing.clone().try_lock_owned().ok()?;
+            running.cancelled.store(true, Ordering::SeqCst);
+            running.notify.notify_one();
+            (running.handle.clone(), killing)
+        };
+        let killed = kill(handle).await;
+        drop(killing);
+        Some(killed)
+    }
+}
+
+pub struct QueryGuard<H: Clone> {
+    queries: Arc<Mutex<HashMap<String, RunningQuery<H>>>>,
+    query_id: Option<String>,
+    cancelled: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+    killing: Arc<AsyncMutex<()>>,
+}
+
+impl<H: Clone> QueryGuard<H> {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /**
+     * Resolves once the query is cancelled. For drivers that can't stop a query server-side,
+     * race this against the query to abandon it.
+     */
+    pub async fn cancelled(&self) {
+        if !self.is_cancelled() {
+            self.notify.notified().await;
+        }
+    }
+
+    /**
+     * Ends the query once a kill sent to it reached the server. Drivers stopping queries with
+     * kill_with go through it before their connection runs anything else, as a mere drop (e.g.
+     * of an aborted query) can't wait.
+     */
+    pub async fn finish(self) {
+        let _killed = self.killing.lock().await;
+    }
+
+    /**
+     * Once a query was cancelled, whatever error the driver raised (e.g. MySQL's "Query execution
+     * was interrupted") is reported as the cancellation itself.
+     */
+    pub fn error(&self, err: AppError) -> AppError {
+        if self.is_cancelled() {
+            AppError::cancelled()
+        } else {
+            err
+        }
+    }
+}
+
+impl<H: Clone> Drop for QueryGuard<H> {
+    fn drop(&mut self) {
+        if let (Some(query_id), Ok(mut queries)) = (&self.query_id, self.queries.lock()) {
+            queries.remove(query_id);
+        }
+    }
+}
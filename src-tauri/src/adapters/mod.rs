@@ -1,19 +1,30 @@
-use std::collections::HashMap;
-use std::time::SystemTime;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use serde::Serialize;
 pub use serde_json::Map as JsonMap;
 pub use serde_json::Value as JsonValue;
 
+use crate::error::ErrorKind;
+use crate::logger;
 use crate::ssh;
-use crate::ssh::SshOpts;
+use crate::ssh::{SshContext, SshOpts, Tunnel};
 use crate::AppError;
 
+mod cancel;
+mod mongo;
 mod mysql;
+mod pinned;
+mod postgres;
 mod sqlite;
+mod transaction;
 
+use mongo::MongoAdapter;
 use mysql::MySQLAdapter;
+use postgres::PostgresAdapter;
 use sqlite::SQLiteAdapter;
+pub use transaction::{AutoRollbackListener, TransactionInfo};
+use transaction::{Idle, Transaction};
 
 /**
  * @see https://github.com/jasondeewright/sqlx
@@ -40,61 +51,312 @@ pub struct AdapterOpts {
     pub user: String,
     pub password: String,
     pub filepath: String,
+    #[serde(default)]
+    pub database: Option<String>,
 }
 
+/**
+ * Column metadata as reported by the driver. Anything the driver doesn't know is left as None.
+ */
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct QueryColumn {
+    pub name: String,
+    pub type_name: String,
+    pub nullable: Option<bool>,
+    pub length: Option<u64>,
+    pub precision: Option<u32>,
+    pub scale: Option<u32>,
+    pub primary_key: Option<bool>,
+    pub auto_increment: Option<bool>,
+    pub table: Option<String>,
+}
+impl QueryColumn {
+    pub fn new(name: String, type_name: String) -> QueryColumn {
+        QueryColumn {
+            name: name,
+            type_name: type_name,
+            ..<_>::default()
+        }
+    }
+}
+
+/**
+ * A warning raised by a statement, e.g. a value truncated on INSERT.
+ */
+#[derive(Serialize, Debug, Clone)]
+pub struct QueryWarning {
+    pub level: String,
+    pub code: Option<u32>,
+    pub message: String,
+}
+
+/**
+ * What a statement did besides returning rows, e.g. the rows an UPDATE changed.
+ * Whatever the driver doesn't report is None. warnings may hold fewer entries than
+ * warning_count when the driver can't list them.
+ */
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct StatementInfo {
+    pub affected_rows: Option<u64>,
+    pub last_insert_id: Option<u64>,
+    pub warning_count: Option<u64>,
+    pub warnings: Vec<QueryWarning>,
+}
+
+/**
+ * The result of one statement. A script yields one per statement, in order, with result_index
+ * being the position in that list.
+ *
+ * Rows are positional: rows[n][i] is the value of columns[i]. This keeps the column order the
+ * driver reported and allows duplicate names, e.g. `SELECT a.id, b.id`.
+ * fields is kept alongside columns as the plain list of column names.
+ *
+ * num_rows only counts the rows returned. What a write statement did is in affected_rows,
+ * last_insert_id and warnings.
+ *
+ * When only a page of the result was read, truncated is set and the rest can be fetched through
+ * cursor_id. total_rows is the size of the whole result, if known.
+ */
 #[derive(Serialize, Debug)]
 pub struct QueryResult {
+    pub result_index: usize,
     pub elapsed_ms: String,
     pub num_rows: String,
-    // fields: Vec<String>,
     pub fields: Vec<String>,
-    pub rows: Vec<HashMap<String, JsonValue>>,
+    pub columns: Vec<QueryColumn>,
+    pub rows: Vec<Vec<JsonValue>>,
+    pub affected_rows: Option<String>,
+    pub last_insert_id: Option<String>,
+    pub warning_count: Option<String>,
+    pub warnings: Vec<QueryWarning>,
+    pub truncated: bool,
+    pub total_rows: Option<String>,
+    pub cursor_id: Option<String>,
 }
 impl QueryResult {
-    pub fn make(start_time: SystemTime, rows: Vec<HashMap<String, JsonValue>>) -> QueryResult {
-        let fields: Vec<String> = if !rows.is_empty() {
-            Vec::from_iter(rows[0].clone().into_keys().into_iter())
-        } else {
-            Vec::new()
-        };
-
+    pub fn make(
+        start_time: SystemTime,
+        columns: Vec<QueryColumn>,
+        rows: Vec<Vec<JsonValue>>,
+    ) -> QueryResult {
         QueryResult {
+            result_index: 0,
             elapsed_ms: start_time
                 .elapsed()
                 .expect("Error parsing elapsed timestamp!")
                 .as_millis()
                 .to_string(),
             num_rows: rows.len().to_string(),
+            affected_rows: None,
+            last_insert_id: None,
+            warning_count: None,
+            warnings: Vec::new(),
+            truncated: false,
+            total_rows: Some(rows.len().to_string()),
+            cursor_id: None,
             rows: rows,
-            fields: fields.into(),
+            fields: columns.iter().map(|column| column.name.clone()).collect(),
+            columns: columns,
+        }
+    }
+
+    pub fn with_info(self, info: &StatementInfo) -> QueryResult {
+        QueryResult {
+            affected_rows: info.affected_rows.map(|count| count.to_string()),
+            last_insert_id: info.last_insert_id.map(|id| id.to_string()),
+            warning_count: info.warning_count.map(|count| count.to_string()),
+            warnings: info.warnings.clone(),
+            ..self
         }
     }
 }
 
+/**
+ * Receives a query's output as the driver reads it. For every statement, columns() is called
+ * once, then row() for each row, then end_statement(), including for statements without rows.
+ * The driver doesn't read the next row until row() resolves, so a sink can pause the query.
+ * Returning an error stops the query, e.g. when the window receiving the rows went away.
+ */
+pub trait QuerySink {
+    async fn columns(&mut self, columns: Vec<QueryColumn>) -> Result<(), AppError>;
+    async fn row(&mut self, row: Vec<JsonValue>) -> Result<(), AppError>;
+    async fn end_statement(&mut self, info: StatementInfo) -> Result<(), AppError>;
+    /**
+     * Called before the columns by drivers that know the size of the result upfront.
+     */
+    fn total_rows(&mut self, _total_rows: usize) {}
+}
+
+/**
+ * Buffers every result in memory.
+ */
+pub struct CollectSink {
+    pub results: Vec<QueryResult>,
+    start_time: SystemTime,
+    columns: Vec<QueryColumn>,
+    rows: Vec<Vec<JsonValue>>,
+}
+impl Default for CollectSink {
+    fn default() -> Self {
+        CollectSink {
+            results: Vec::new(),
+            start_time: SystemTime::now(),
+            columns: Vec::new(),
+            rows: Vec::new(),
+        }
+    }
+}
+impl QuerySink for CollectSink {
+    async fn columns(&mut self, columns: Vec<QueryColumn>) -> Result<(), AppError> {
+        self.columns = columns;
+        Ok(())
+    }
+    async fn row(&mut self, row: Vec<JsonValue>) -> Result<(), AppError> {
+        self.rows.push(row);
+        Ok(())
+    }
+    async fn end_statement(&mut self, info: StatementInfo) -> Result<(), AppError> {
+        let columns = std::mem::take(&mut self.columns);
+        let rows = std::mem::take(&mut self.rows);
+        let result = QueryResult {
+            result_index: self.results.len(),
+            ..QueryResult::make(self.start_time, columns, rows).with_info(&info)
+        };
+        self.results.push(result);
+        self.start_time = SystemTime::now();
+        Ok(())
+    }
+}
+
+/**
+ * Binary values are tagged so the frontend can tell them apart from text, e.g.
+ * { "type": "binary", "size": 4, "hex": "deadbeef" }
+ */
+pub fn binary_to_json(bytes: &[u8]) -> JsonValue {
+    serde_json::json!({
+        "type": "binary",
+        "size": bytes.len(),
+        "hex": hex::encode(bytes),
+    })
+}
+
+/**
+ * Widening an f32 straight to f64 exposes representation noise (0.1 -> 0.10000000149011612),
+ * so round-trip through the shortest decimal form instead.
+ */
+pub fn float_to_json(value: f32) -> JsonValue {
+    value
+        .to_string()
+        .parse::<f64>()
+        .map(JsonValue::from)
+        .unwrap_or(JsonValue::Null)
+}
+
 #[derive(Clone)]
 pub enum AdapterEnum {
     SQLite(SQLiteAdapter),
     MySQL(MySQLAdapter),
+    PostgreSQL(PostgresAdapter),
+    MongoDB(MongoAdapter),
+}
+
+impl AdapterEnum {
+    /**
+     * MongoDB commands don't depend on the connection they run on, so there is nothing to pin.
+     */
+    async fn pin(&self) -> Result<AdapterEnum, AppError> {
+        match self {
+            AdapterEnum::MySQL(adapter) => Ok(AdapterEnum::MySQL(adapter.pin().await?)),
+            AdapterEnum::SQLite(adapter) => Ok(AdapterEnum::SQLite(adapter.pin().await?)),
+            AdapterEnum::PostgreSQL(adapter) => Ok(AdapterEnum::PostgreSQL(adapter.pin().await?)),
+            AdapterEnum::MongoDB(adapter) => Ok(AdapterEnum::MongoDB(adapter.clone())),
+        }
+    }
+
+    fn pooled(&self) -> AdapterEnum {
+        match self {
+            AdapterEnum::MySQL(adapter) => AdapterEnum::MySQL(adapter.pooled()),
+            AdapterEnum::SQLite(adapter) => AdapterEnum::SQLite(adapter.pooled()),
+            AdapterEnum::PostgreSQL(adapter) => AdapterEnum::PostgreSQL(adapter.pooled()),
+            AdapterEnum::MongoDB(adapter) => AdapterEnum::MongoDB(adapter.clone()),
+        }
+    }
+
+    fn is_pinned(&self) -> bool {
+        match self {
+            AdapterEnum::MySQL(adapter) => adapter.is_pinned(),
+            AdapterEnum::SQLite(adapter) => adapter.is_pinned(),
+            AdapterEnum::PostgreSQL(adapter) => adapter.is_pinned(),
+            AdapterEnum::MongoDB(_) => false,
+        }
+    }
 }
 
 // #[derive(Default, Clone)]
 pub trait Adapter {
     // async fn connect(&self, opts: AdapterOpts) -> Result<Self, AppError> where Self: Sized;
-    async fn query(&self, query: String, database: Option<String>)
-        -> Result<QueryResult, AppError>;
+    /**
+     * Runs a query, handing every row to the sink as soon as it is read. When a query_id is
+     * given, the query can be stopped with cancel(query_id), in which case this returns
+     * AppError::cancelled().
+     */
+    async fn query_stream<S: QuerySink + Send>(
+        &self,
+        query: String,
+        database: Option<String>,
+        query_id: Option<String>,
+        sink: &mut S,
+    ) -> Result<(), AppError>;
+
+    /**
+     * Runs a query and buffers the whole result, one QueryResult per statement.
+     */
+    async fn query(
+        &self,
+        query: String,
+        database: Option<String>,
+        query_id: Option<String>,
+    ) -> Result<Vec<QueryResult>, AppError> {
+        let mut sink = CollectSink::default();
+        self.query_stream(query, database, query_id, &mut sink)
+            .await?;
+        Ok(sink.results)
+    }
+
+    async fn cancel(&self, query_id: String) -> Result<bool, AppError>;
     async fn disconnect(&mut self) -> Result<bool, AppError>;
 }
 
 impl Adapter for AdapterEnum {
-    async fn query(
+    async fn query_stream<S: QuerySink + Send>(
         &self,
         query: String,
         database: Option<String>,
-    ) -> Result<QueryResult, AppError> {
+        query_id: Option<String>,
+        sink: &mut S,
+    ) -> Result<(), AppError> {
         match self {
-            AdapterEnum::MySQL(adapter) => adapter.query(query, database).await,
-            AdapterEnum::SQLite(adapter) => adapter.query(query, database).await,
-            // _ => Err(AppError::from("Driver not found!")),
+            AdapterEnum::MySQL(adapter) => {
+                adapter.query_stream(query, database, query_id, sink).await
+            }
+            AdapterEnum::SQLite(adapter) => {
+                adapter.query_stream(query, database, query_id, sink).await
+            }
+            AdapterEnum::PostgreSQL(adapter) => {
+                adapter.query_stream(query, database, query_id, sink).await
+            }
+            AdapterEnum::MongoDB(adapter) => {
+                adapter.query_stream(query, database, query_id, sink).await
+            }
+        }
+    }
+
+    async fn cancel(&self, query_id: String) -> Result<bool, AppError> {
+        match self {
+            AdapterEnum::MySQL(adapter) => adapter.cancel(query_id).await,
+            AdapterEnum::SQLite(adapter) => adapter.cancel(query_id).await,
+            AdapterEnum::PostgreSQL(adapter) => adapter.cancel(query_id).await,
+            AdapterEnum::MongoDB(adapter) => adapter.cancel(query_id).await,
         }
     }
 
@@ -102,32 +364,338 @@ impl Adapter for AdapterEnum {
         match self {
             AdapterEnum::MySQL(adapter) => adapter.disconnect().await,
             AdapterEnum::SQLite(adapter) => adapter.disconnect().await,
+            AdapterEnum::PostgreSQL(adapter) => adapter.disconnect().await,
+            AdapterEnum::MongoDB(adapter) => adapter.disconnect().await,
             // _ => Ok(true),
         }
     }
 }
 
+/**
+ * An adapter, along with the SSH tunnel it connects through. Clones share the tunnel, which is
+ * closed on disconnect (or once the last clone is dropped).
+ */
+#[derive(Clone)]
+pub struct Connection {
+    adapter: AdapterEnum,
+    tunnel: Option<Arc<Tunnel>>,
+    /// only pinned connections can hold a transaction open across queries
+    transaction: Option<Transaction>,
+    /// redacted from the log until the session and its copies are gone
+    secrets: Arc<logger::Secrets>,
+}
+impl Connection {
+    pub fn tunnel(&self) -> Option<&Tunnel> {
+        self.tunnel.as_deref()
+    }
+
+    /**
+     * A copy of the connection keeping one physical connection to itself, e.g. for a tab. What a
+     * query leaves behind on the server (session variables, temporary tables, LAST_INSERT_ID(),
+     * LOCK TABLES...) is then still there for the next one. The pool and tunnel stay shared.
+     */
+    pub async fn pin(&self) -> Result<Connection, AppError> {
+        let adapter = self.adapter.pin().await.map_err(|err| self.error(err))?;
+        Ok(Connection {
+            transaction: match adapter.is_pinned() {
+                true => Some(Transaction::default()),
+                false => None,
+            },
+            adapter: adapter,
+            tunnel: self.tunnel.clone(),
+            secrets: self.secrets.clone(),
+        })
+    }
+
+    /**
+     * A copy running on the pool even if this one is pinned, for lookups (e.g. listing the tables)
+     * that shouldn't wait on the pinned connection, nor see its state.
+     */
+    pub fn pooled(&self) -> Connection {
+        Connection {
+            adapter: self.adapter.pooled(),
+            tunnel: self.tunnel.clone(),
+            transaction: None,
+            secrets: self.secrets.clone(),
+        }
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.adapter.is_pinned()
+    }
+
+    /**
+     * None unless pinned.
+     */
+    pub fn transaction_info(&self) -> Option<TransactionInfo> {
+        self.transaction
+            .as_ref()
+            .map(|transaction| transaction.info())
+    }
+
+    fn pinned_transaction(&self) -> Result<&Transaction, AppError> {
+        self.transaction.as_ref().ok_or_else(|| {
+            AppError::new(
+                ErrorKind::Transaction,
+                "Transactions need a dedicated connection, reconnect with one to use them!",
+            )
+        })
+    }
+
+    async fn execute(&self, statement: String) -> Result<(), AppError> {
+        self.adapter
+            .query(statement, None, None)
+            .await
+            .map(|_| ())
+            .map_err(|err| self.error(err))
+    }
+
+    /**
+     * Opens a transaction spanning the queries to come, until commit or rollback. With
+     * auto_rollback, it is rolled back once no query ran in it for that long, and
+     * on_auto_rollback is told.
+     */
+    pub async fn begin(
+        &self,
+        auto_rollback: Option<Duration>,
+        on_auto_rollback: Option<AutoRollbackListener>,
+    ) -> Result<TransactionInfo, AppError> {
+        let transaction = self.pinned_transaction()?;
+        let id = transaction.open(auto_rollback)?;
+        if let Err(why) = self.execute("BEGIN".to_string()).await {
+            transaction.close();
+            return Err(why);
+        }
+        if auto_rollback.is_some() {
+            self.watch_idle(id, on_auto_rollback);
+        }
+        Ok(transaction.info())
+    }
+
+    /**
+     * The transaction stays open if the commit fails, e.g. to roll it back.
+     */
+    pub async fn commit(&self) -> Result<TransactionInfo, AppError> {
+        let transaction = self.pinned_transaction()?;
+        let _activity = transaction.enter()?;
+        self.execute("COMMIT".to_string()).await?;
+        transaction.close();
+        Ok(transaction.info())
+    }
+
+    /**
+     * Rolls back the whole transaction, or what was done since the savepoint. A failed rollback
+     * still ends the transaction, as the server rolls it back along with the lost connection.
+     */
+    pub async fn rollback(&self, savepoint: Option<String>) -> Result<TransactionInfo, AppError> {
+        let transaction = self.pinned_transaction()?;
+        let _activity = transaction.enter()?;
+        match savepoint {
+            Some(name) => {
+                if !transaction.has_savepoint(&name) {
+                    return Err(AppError::new(
+                        ErrorKind::Transaction,
+                        format!("There is no savepoint {} in the transaction!", name),
+                    ));
+                }
+                self.execute(format!("ROLLBACK TO SAVEPOINT {}", name))
+                    .await?;
+                transaction.rolled_back_to(&name);
+            }
+            None => {
+                let rolled_back = self.execute("ROLLBACK".to_string()).await;
+                transaction.close();
+                rolled_back?;
+            }
+        }
+        Ok(transaction.info())
+    }
+
+    /**
+     * Savepoint names are plain identifiers, e.g. before_cleanup.
+     */
+    pub async fn savepoint(&self, name: String) -> Result<TransactionInfo, AppError> {
+        let transaction = self.pinned_transaction()?;
+        let _activity = transaction.enter()?;
+        let is_identifier = name
+            .chars()
+            .next()
+            .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_identifier {
+            return Err(AppError::new(
+                ErrorKind::Syntax,
+                format!("{} is not a valid savepoint name!", name),
+            ));
+        }
+        self.execute(format!("SAVEPOINT {}", name)).await?;
+        transaction.add_savepoint(&name);
+        Ok(transaction.info())
+    }
+
+    /**
+     * Rolls the transaction back once it stayed idle past its timeout. Stops watching once the
+     * transaction ended some other way.
+     */
+    fn watch_idle(&self, id: String, on_auto_rollback: Option<AutoRollbackListener>) {
+        let connection = self.clone();
+        tokio::spawn(async move {
+            let transaction = match connection.transaction.as_ref() {
+                Some(transaction) => transaction,
+                None => return,
+            };
+            let info = loop {
+                match transaction.idle(&id) {
+                    Idle::Ended => return,
+                    Idle::Wait(remaining) => tokio::time::sleep(remaining).await,
+                    Idle::Expired => {
+                        // a COMMIT or query may have started since, then it is no longer idle.
+                        if let Some(info) = transaction.take_if_expired(&id) {
+                            break info;
+                        }
+                    }
+                }
+            };
+            log::warn!(
+                "Rolling back a transaction idle for {}s",
+                info.idle_secs.unwrap_or_default()
+            );
+            // the transaction was taken before anything is awaited, so a commit, rollback or
+            // savepoint coming in meanwhile finds it closed, and a transaction begun after it
+            // is left alone.
+            let rolled_back = connection.execute("ROLLBACK".to_string()).await;
+            match rolled_back {
+                Ok(_) => {
+                    if let Some(on_auto_rollback) = on_auto_rollback {
+                        on_auto_rollback(info);
+                    }
+                }
+                Err(why) => log::warn!("Unable to roll back the idle transaction: {}", why),
+            }
+        });
+    }
+
+    fn error(&self, err: AppError) -> AppError {
+        tunnel_error(self.tunnel.as_deref(), err)
+    }
+}
+
+/**
+ * Once the tunnel failed, whatever the driver raised (e.g. a lost connection) is reported as the
+ * tunnel's error instead.
+ */
+fn tunnel_error(tunnel: Option<&Tunnel>, err: AppError) -> AppError {
+    match tunnel.and_then(|tunnel| tunnel.error()) {
+        Some(tunnel_error) if !err.is_cancelled() => AppError::new(
+            ErrorKind::Tunnel,
+            format!("The SSH tunnel failed: {}", tunnel_error),
+        ),
+        _ => err,
+    }
+}
+impl Adapter for Connection {
+    async fn query_stream<S: QuerySink + Send>(
+        &self,
+        query: String,
+        database: Option<String>,
+        query_id: Option<String>,
+        sink: &mut S,
+    ) -> Result<(), AppError> {
+        let _activity = self
+            .transaction
+            .as_ref()
+            .map(|transaction| transaction.activity());
+        self.adapter
+            .query_stream(query, database, query_id, sink)
+            .await
+            .map_err(|err| self.error(err))
+    }
+
+    async fn cancel(&self, query_id: String) -> Result<bool, AppError> {
+        self.adapter
+            .cancel(query_id)
+            .await
+            .map_err(|err| self.error(err))
+    }
+
+    /**
+     * Closes the tunnel after the adapter, even if the adapter fails to disconnect cleanly.
+     * An open transaction is rolled back by the server as the connection closes.
+     */
+    async fn disconnect(&mut self) -> Result<bool, AppError> {
+        if let Some(transaction) = self
+            .transaction
+            .as_ref()
+            .filter(|transaction| transaction.is_open())
+        {
+            log::warn!("Disconnecting with an uncommitted transaction, it is rolled back");
+            transaction.close();
+        }
+        let disconnected = self.adapter.disconnect().await;
+        if let Some(tunnel) = self.tunnel.take() {
+            tunnel.close().await.map_err(|why| {
+                AppError::new(ErrorKind::Tunnel, format!("The SSH tunnel failed: {}", why))
+            })?;
+        }
+        disconnected
+    }
+}
+
+/**
+ * With ssh_opts, the database is reached through a tunnel over the chain of SSH hops, the last
+ * hop connecting to the database host. The ssh_context is only used when tunnelling, e.g. to ask
+ * the user about unknown host keys.
+ */
 pub async fn connect_adapter(
     opts: AdapterOpts,
     ssh_opts: Option<SshOpts>,
-) -> Result<AdapterEnum, AppError> {
-    let driver_opts: AdapterOpts = match ssh_opts {
+    ssh_context: SshContext,
+) -> Result<Connection, AppError> {
+    let secrets = Arc::new(logger::Secrets::register(
+        std::iter::once(opts.password.clone())
+            .chain(
+                ssh_opts
+                    .iter()
+                    .flat_map(|ssh_opts| ssh_opts.hops.iter())
+                    .map(|hop| hop.password.clone()),
+            )
+            .collect(),
+    ));
+
+    let (driver_opts, tunnel) = match ssh_opts {
         Some(ssh_opts_actual) => {
-            let tunnel = ssh::jump(ssh_opts_actual, opts.host, opts.port)
+            let tunnel = ssh::jump(ssh_opts_actual, ssh_context, opts.host.clone(), opts.port)
                 .await
-                .map_err(AppError::from)?;
-            AdapterOpts {
+                .map_err(AppError::ssh)?;
+            let driver_opts = AdapterOpts {
                 host: "127.0.0.1".to_string(),
-                port: tunnel.port() as u32,
+                port: tunnel.local_addr().port() as u32,
                 ..opts
-            }
+            };
+            (driver_opts, Some(Arc::new(tunnel)))
         }
-        None => opts,
+        None => (opts, None),
     };
 
+    let adapter = connect_driver(driver_opts)
+        .await
+        .map_err(|err| tunnel_error(tunnel.as_deref(), err))?;
+    Ok(Connection {
+        adapter: adapter,
+        tunnel: tunnel,
+        transaction: None,
+        secrets: secrets,
+    })
+}
+
+async fn connect_driver(driver_opts: AdapterOpts) -> Result<AdapterEnum, AppError> {
     match driver_opts.driver {
         DriverType::MySQL => Ok(AdapterEnum::MySQL(mysql::connect(driver_opts).await?)),
         DriverType::SQLite => Ok(AdapterEnum::SQLite(sqlite::connect(driver_opts).await?)),
+        DriverType::PostgreSQL => Ok(AdapterEnum::PostgreSQL(
+            postgres::connect(driver_opts).await?,
+        )),
+        DriverType::MongoDB => Ok(AdapterEnum::MongoDB(mongo::connect(driver_opts).await?)),
         _ => Err(AppError::from("Unable to connect: unknown driver!")),
     }
 }
@@ -0,0 +1,626 @@
+use futures::stream::{self, BoxStream};
+use futures::{StreamExt, TryStreamExt};
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::{ClientOptions, Credential, ServerAddress};
+use mongodb::Client;
+pub use serde_json::Value as JsonValue;
+
+use super::cancel::RunningQueries;
+use super::{
+    binary_to_json, Adapter, AdapterOpts, QueryColumn, QuerySink, QueryWarning, StatementInfo,
+};
+use crate::AppError;
+
+const DEFAULT_DATABASE: &str = "test";
+/// documents read ahead of the rows to find the columns
+const SAMPLE_SIZE: usize = 100;
+
+pub async fn connect(opts: AdapterOpts) -> Result<MongoAdapter, AppError>
+where
+    MongoAdapter: Sized,
+{
+    let mut client_opts = ClientOptions::default();
+    client_opts.hosts = vec![ServerAddress::Tcp {
+        host: opts.host,
+        port: Some(opts.port as u16),
+    }];
+    // never follow replica set discovery: the advertised members are not reachable through a tunnel.
+    client_opts.direct_connection = Some(true);
+    client_opts.app_name = Some("Torqle".to_string());
+
+    if !opts.user.is_empty() {
+        let mut credential = Credential::default();
+        credential.username = Some(opts.user);
+        credential.password = Some(opts.password);
+        client_opts.credential = Some(credential);
+    }
+
+    let client = Client::with_options(client_opts).map_err(AppError::from)?;
+
+    // the driver connects lazily, so ping in order to validate the host and credentials.
+    client
+        .database("admin")
+        .run_command(doc! { "ping": 1 })
+        .await
+        .map_err(AppError::from)?;
+
+    Ok(MongoAdapter {
+        client: client,
+        database: opts
+            .database
+            .filter(|database| !database.is_empty())
+            .unwrap_or(DEFAULT_DATABASE.to_string()),
+        running: RunningQueries::default(),
+    })
+}
+
+#[derive(Clone)]
+pub struct MongoAdapter {
+    client: Client,
+    database: String,
+    running: RunningQueries<()>,
+}
+impl Adapter for MongoAdapter {
+    /**
+     * Accepts either a shell-style command, e.g. `db.users.find({ age: { $gt: 21 } }).limit(10)`,
+     * or a JSON command document, e.g. `{ "find": "users", "filter": {}, "limit": 10 }`.
+     */
+    async fn query_stream<S: QuerySink + Send>(
+        &self,
+        query: String,
+        database: Option<String>,
+        query_id: Option<String>,
+        sink: &mut S,
+    ) -> Result<(), AppError> {
+        let command = MongoCommand::parse(query.as_str())?;
+        let guard = self.running.start(query_id, ());
+
+        // the driver has no handle to stop an operation, so a cancelled command is abandoned.
+        tokio::select! {
+            streamed = self.stream(command, database, sink) => streamed,
+            _ = guard.cancelled() => Err(AppError::cancelled()),
+        }
+    }
+
+    async fn cancel(&self, query_id: String) -> Result<bool, AppError> {
+        Ok(self.running.cancel(query_id.as_str()).is_some())
+    }
+
+    async fn disconnect(&mut self) -> Result<bool, AppError> {
+        self.client.clone().shutdown().await;
+        Ok(true)
+    }
+}
+impl MongoAdapter {
+    /**
+     * Documents are handed over as the driver reads them, so a cursor only pulls the batches
+     * of the pages it returns. The columns are the keys of the first documents, read ahead.
+     */
+    async fn stream<S: QuerySink + Send>(
+        &self,
+        command: MongoCommand,
+        database: Option<String>,
+        sink: &mut S,
+    ) -> Result<(), AppError> {
+        let mut documents = self.run(command, database).await?;
+
+        let mut sample: Vec<Document> = Vec::new();
+        while sample.len() < SAMPLE_SIZE {
+            match documents.try_next().await.map_err(AppError::from)? {
+                Some(document) => sample.push(document),
+                None => break,
+            }
+        }
+        let columns = columns_of(&sample);
+        let keys: Vec<String> = columns.iter().map(|column| column.name.clone()).collect();
+        sink.columns(columns).await?;
+
+        let mut num_unlisted = 0;
+        for document in sample {
+            sink.row(document_row(document, &keys)).await?;
+        }
+        while let Some(document) = documents.try_next().await.map_err(AppError::from)? {
+            if document.keys().any(|key| !keys.contains(key)) {
+                num_unlisted += 1;
+            }
+            sink.row(document_row(document, &keys)).await?;
+        }
+
+        let mut info = StatementInfo::default();
+        if num_unlisted > 0 {
+            info.warning_count = Some(1);
+            info.warnings.push(QueryWarning {
+                level: "Warning".to_string(),
+                code: None,
+                message: format!(
+                    "{} documents have fields missing from the first {} documents, which are not shown. Add a projection to list them.",
+                    num_unlisted, SAMPLE_SIZE
+                ),
+            });
+        }
+        sink.end_statement(info).await
+    }
+
+    async fn run(
+        &self,
+        command: MongoCommand,
+        database: Option<String>,
+    ) -> Result<BoxStream<'static, Result<Document, mongodb::error::Error>>, AppError> {
+        let db = self
+            .client
+            .database(database.as_deref().unwrap_or(self.database.as_str()));
+
+        let documents = match command {
+            MongoCommand::Find {
+                collection,
+                filter,
+                projection,
+                sort,
+                limit,
+                skip,
+            } => {
+                let collection = db.collection::<Document>(&collection);
+                let mut find = collection.find(filter);
+                if let Some(projection) = projection {
+                    find = find.projection(projection);
+                }
+                if let Some(sort) = sort {
+                    find = find.sort(sort);
+                }
+                if let Some(limit) = limit {
+                    find = find.limit(limit);
+                }
+                if let Some(skip) = skip {
+                    find = find.skip(skip);
+                }
+                find.await.map_err(AppError::from)?.boxed()
+            }
+            MongoCommand::Aggregate {
+                collection,
+                pipeline,
+            } => db
+                .collection::<Document>(&collection)
+                .aggregate(pipeline)
+                .await
+                .map_err(AppError::from)?
+                .boxed(),
+            MongoCommand::CountDocuments { collection, filter } => {
+                let count = db
+                    .collection::<Document>(&collection)
+                    .count_documents(filter)
+                    .await
+                    .map_err(AppError::from)?;
+                stream::iter([Ok(doc! { "count": count as i64 })]).boxed()
+            }
+            MongoCommand::RunCommand(command) => {
+                let reply = db.run_command(command).await.map_err(AppError::from)?;
+                stream::iter([Ok(reply)]).boxed()
+            }
+        };
+
+        Ok(documents)
+    }
+}
+
+/**
+ * A command is parsed once per query, so the size of the find variant doesn't matter.
+ */
+#[derive(Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+enum MongoCommand {
+    Find {
+        collection: String,
+        filter: Document,
+        projection: Option<Document>,
+        sort: Option<Document>,
+        limit: Option<i64>,
+        skip: Option<u64>,
+    },
+    Aggregate {
+        collection: String,
+        pipeline: Vec<Document>,
+    },
+    CountDocuments {
+        collection: String,
+        filter: Document,
+    },
+    RunCommand(Document),
+}
+impl MongoCommand {
+    fn parse(query: &str) -> Result<MongoCommand, AppError> {
+        let query = query.trim().trim_end_matches(';').trim();
+
+        if query.starts_with('{') {
+            let command = match parse_args(query)?.pop() {
+                Some(Bson::Document(command)) => command,
+                _ => return Err(AppError::from("The command must be a JSON object")),
+            };
+            return MongoCommand::from_document(command);
+        }
+
+        match query.strip_prefix("db.") {
+            Some(shell) => MongoCommand::from_shell(shell),
+            None => Err(AppError::from(
+                "Unable to parse command. Expected `db.<collection>.<method>(...)` or a JSON command document.",
+            )),
+        }
+    }
+
+    /**
+     * find and aggregate command documents are routed through the collection API so they return
+     * every batch of the cursor, not just the first one.
+     */
+    fn from_document(command: Document) -> Result<MongoCommand, AppError> {
+        let name = command.keys().next().cloned().unwrap_or_default();
+
+        match (name.as_str(), command.get_str(&name)) {
+            ("find", Ok(collection)) => Ok(MongoCommand::Find {
+                collection: collection.to_string(),
+                filter: command.get_document("filter").cloned().unwrap_or_default(),
+                projection: command.get_document("projection").ok().cloned(),
+                sort: command.get_document("sort").ok().cloned(),
+                limit: command.get("limit").and_then(bson_to_i64),
+                skip: command
+                    .get("skip")
+                    .and_then(bson_to_i64)
+                    .map(|skip| skip as u64),
+            }),
+            ("aggregate", Ok(collection)) => Ok(MongoCommand::Aggregate {
+                collection: collection.to_string(),
+                pipeline: documents_from(command.get("pipeline").cloned())?,
+            }),
+            ("countDocuments", Ok(collection)) => Ok(MongoCommand::CountDocuments {
+                collection: collection.to_string(),
+                filter: command.get_document("filter").cloned().unwrap_or_default(),
+            }),
+            _ => Ok(MongoCommand::RunCommand(command)),
+        }
+    }
+
+    fn from_shell(shell: &str) -> Result<MongoCommand, AppError> {
+        let mut calls = split_calls(shell)?.into_iter();
+
+        let (collection, method, args) = match calls.next() {
+            Some((name, Some(args))) if name == "runCommand" => {
+                return match parse_args(&args)?.into_iter().next() {
+                    Some(Bson::Document(command)) => Ok(MongoCommand::RunCommand(command)),
+                    _ => Err(AppError::from("db.runCommand() expects a command document")),
+                };
+            }
+            Some((name, Some(args))) if name == "getCollection" => {
+                let collection = match parse_args(&args)?.into_iter().next() {
+                    Some(Bson::String(collection)) => collection,
+                    _ => return Err(AppError::from("db.getCollection() expects a name")),
+                };
+                match calls.next() {
+                    Some((method, Some(args))) => (collection, method, args),
+                    _ => return Err(AppError::from("Missing a method call on the collection")),
+                }
+            }
+            Some((collection, None)) => match calls.next() {
+                Some((method, Some(args))) => (collection, method, args),
+                _ => return Err(AppError::from("Missing a method call on the collection")),
+            },
+            _ => return Err(AppError::from("Unable to parse the collection name")),
+        };
+
+        let mut args = parse_args(&args)?.into_iter();
+        let mut command = match method.as_str() {
+            "find" => MongoCommand::Find {
+                collection: collection,
+                filter: document_from(args.next())?,
+                projection: args.next().map(|arg| document_from(Some(arg))).transpose()?,
+                sort: None,
+                limit: None,
+                skip: None,
+            },
+            "aggregate" => MongoCommand::Aggregate {
+                collection: collection,
+                pipeline: documents_from(args.next())?,
+            },
+            "countDocuments" => MongoCommand::CountDocuments {
+                collection: collection,
+                filter: document_from(args.next())?,
+            },
+            _ => {
+                return Err(AppError::from(format!(
+                    "Unsupported method '{}'. Supported methods are find, aggregate, countDocuments and runCommand.",
+                    method
+                )))
+            }
+        };
+
+        // cursor modifiers chained onto find(), e.g. .sort({ name: 1 }).limit(10)
+        for (modifier, modifier_args) in calls {
+            let arg = parse_args(&modifier_args.unwrap_or_default())?
+                .into_iter()
+                .next();
+            match (&mut command, modifier.as_str()) {
+                (MongoCommand::Find { sort, .. }, "sort") => *sort = Some(document_from(arg)?),
+                (MongoCommand::Find { projection, .. }, "projection") => {
+                    *projection = Some(document_from(arg)?)
+                }
+                (MongoCommand::Find { limit, .. }, "limit") => {
+                    *limit = arg.as_ref().and_then(bson_to_i64)
+                }
+                (MongoCommand::Find { skip, .. }, "skip") => {
+                    *skip = arg.as_ref().and_then(bson_to_i64).map(|skip| skip as u64)
+                }
+                _ => {
+                    return Err(AppError::from(format!(
+                        "Unsupported modifier '.{}()'",
+                        modifier
+                    )))
+                }
+            }
+        }
+
+        Ok(command)
+    }
+}
+
+/**
+ * Splits `users.find({...}).limit(10)` into [("users", None), ("find", Some("{...}")), ("limit", Some("10"))]
+ */
+fn split_calls(shell: &str) -> Result<Vec<(String, Option<String>)>, AppError> {
+    let mut calls: Vec<(String, Option<String>)> = Vec::new();
+    let mut chars = shell.chars().peekable();
+
+    loop {
+        let name: String = std::iter::from_fn(|| chars.next_if(|c| *c != '.' && *c != '('))
+            .collect::<String>()
+            .trim()
+            .to_string();
+        if name.is_empty() {
+            return Err(AppError::from("Unable to parse command: expected a name"));
+        }
+
+        let args = if chars.next_if_eq(&'(').is_some() {
+            let mut depth = 1;
+            let mut quote: Option<char> = None;
+            let mut args = String::new();
+            while let Some(c) = chars.next() {
+                match (quote, c) {
+                    (Some(_), '\\') => {
+                        args.push(c);
+                        if let Some(escaped) = chars.next() {
+                            args.push(escaped);
+                        }
+                        continue;
+                    }
+                    (Some(q), _) if c == q => quote = None,
+                    (Some(_), _) => (),
+                    (None, '"' | '\'') => quote = Some(c),
+                    (None, '(' | '[' | '{') => depth += 1,
+                    (None, ')' | ']' | '}') => depth -= 1,
+                    _ => (),
+                }
+                if depth == 0 {
+                    break;
+                }
+                args.push(c);
+            }
+            if depth != 0 {
+                return Err(AppError::from(
+                    "Unable to parse command: unbalanced parentheses",
+                ));
+            }
+            Some(args)
+        } else {
+            None
+        };
+
+        calls.push((name, args));
+
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.next() {
+            Some('.') => continue,
+            None => break,
+            Some(c) => {
+                return Err(AppError::from(format!(
+                    "Unable to parse command: unexpected '{}'",
+                    c
+                )))
+            }
+        }
+    }
+
+    Ok(calls)
+}
+
+/**
+ * Parses a comma-separated list of shell arguments. The mongo shell accepts relaxed javascript
+ * (unquoted keys, single quotes, ObjectId("...")), so normalize it into extended JSON first.
+ */
+fn parse_args(args: &str) -> Result<Vec<Bson>, AppError> {
+    let json = format!("[{}]", normalize_shell_json(args));
+    let value: JsonValue = serde_json::from_str(json.as_str())
+        .map_err(|why| AppError::from(format!("Unable to parse command arguments: {}", why)))?;
+
+    match Bson::try_from(value) {
+        Ok(Bson::Array(args)) => Ok(args),
+        Ok(_) => Ok(Vec::new()),
+        Err(why) => Err(AppError::from(format!(
+            "Unable to parse command arguments: {}",
+            why
+        ))),
+    }
+}
+
+fn normalize_shell_json(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    // closing text to emit for each open parenthesis, e.g. "}" for ObjectId("...")
+    let mut wrappers: Vec<&str> = Vec::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                output.push('"');
+                while let Some(inner) = chars.next() {
+                    match inner {
+                        '\\' => {
+                            output.push('\\');
+                            if let Some(escaped) = chars.next() {
+                                output.push(escaped);
+                            }
+                        }
+                        '"' if c == '\'' => output.push_str("\\\""),
+                        _ if inner == c => break,
+                        _ => output.push(inner),
+                    }
+                }
+                output.push('"');
+            }
+            c if c.is_alphabetic() || c == '_' || c == '$' => {
+                let mut word = String::from(c);
+                while let Some(next) =
+                    chars.next_if(|n| n.is_alphanumeric() || *n == '_' || *n == '$')
+                {
+                    word.push(next);
+                }
+                while chars.next_if(|n| n.is_whitespace()).is_some() {}
+
+                match (word.as_str(), chars.peek()) {
+                    (_, Some(':')) => output.push_str(&format!("\"{}\"", word)),
+                    ("new", _) => (),
+                    ("ObjectId", Some('(')) => {
+                        chars.next();
+                        output.push_str("{\"$oid\":");
+                        wrappers.push("}");
+                    }
+                    ("ISODate" | "Date", Some('(')) => {
+                        chars.next();
+                        output.push_str("{\"$date\":");
+                        wrappers.push("}");
+                    }
+                    ("NumberDecimal", Some('(')) => {
+                        chars.next();
+                        output.push_str("{\"$numberDecimal\":");
+                        wrappers.push("}");
+                    }
+                    ("NumberLong" | "NumberInt", Some('(')) => {
+                        chars.next();
+                        // the value is a string in extended JSON, whether the shell quoted it or not
+                        let mut number = String::new();
+                        while let Some(next) = chars.next_if(|n| *n != ')') {
+                            if !next.is_whitespace() && next != '"' && next != '\'' {
+                                number.push(next);
+                            }
+                        }
+                        chars.next();
+                        let key = match word.as_str() {
+                            "NumberLong" => "$numberLong",
+                            _ => "$numberInt",
+                        };
+                        output.push_str(&format!("{{\"{}\":\"{}\"}}", key, number));
+                    }
+                    _ => output.push_str(&word),
+                }
+                // keep the whitespace we skipped so numbers and words are not glued together
+                output.push(' ');
+            }
+            '(' => wrappers.push(""),
+            ')' => output.push_str(wrappers.pop().unwrap_or_default()),
+            _ => output.push(c),
+        }
+    }
+
+    output
+}
+
+fn document_from(arg: Option<Bson>) -> Result<Document, AppError> {
+    match arg {
+        Some(Bson::Document(document)) => Ok(document),
+        None | Some(Bson::Null) => Ok(Document::new()),
+        Some(other) => Err(AppError::from(format!(
+            "Expected a document but found {}",
+            other
+        ))),
+    }
+}
+
+fn documents_from(arg: Option<Bson>) -> Result<Vec<Document>, AppError> {
+    match arg {
+        Some(Bson::Array(items)) => items
+            .into_iter()
+            .map(|item| document_from(Some(item)))
+            .collect(),
+        None => Ok(Vec::new()),
+        Some(other) => Err(AppError::from(format!(
+            "Expected an array of documents but found {}",
+            other
+        ))),
+    }
+}
+
+fn bson_to_i64(value: &Bson) -> Option<i64> {
+    match value {
+        Bson::Int32(v) => Some(*v as i64),
+        Bson::Int64(v) => Some(*v),
+        Bson::Double(v) => Some(*v as i64),
+        _ => None,
+    }
+}
+
+/**
+ * The columns are every top-level key seen across the documents, in the order they were first
+ * seen, so heterogeneous documents still line up into columns.
+ * Collections are schemaless, so a column's type is the BSON type of the first non-null value.
+ */
+fn columns_of(documents: &[Document]) -> Vec<QueryColumn> {
+    let mut columns: Vec<QueryColumn> = Vec::new();
+    for document in documents.iter() {
+        for (key, value) in document.iter() {
+            let position = match columns.iter().position(|column| column.name == *key) {
+                Some(position) => position,
+                None => {
+                    columns.push(QueryColumn::new(key.clone(), String::new()));
+                    columns.len() - 1
+                }
+            };
+            let column = &mut columns[position];
+            if column.type_name.is_empty() && *value != Bson::Null {
+                column.type_name = format!("{:?}", value.element_type());
+            }
+            if *value == Bson::Null {
+                column.nullable = Some(true);
+            }
+        }
+    }
+    columns
+}
+
+/**
+ * Picks the values of the listed keys, a missing key reads as null.
+ */
+fn document_row(mut document: Document, keys: &[String]) -> Vec<JsonValue> {
+    keys.iter()
+        .map(|key| {
+            document
+                .remove(key)
+                .map(bson_to_json)
+                .unwrap_or(JsonValue::Null)
+        })
+        .collect()
+}
+
+/**
+ * Scalars are rendered as plain values. Nested documents and arrays are kept as (relaxed
+ * extended) JSON values so the grid can show them as-is.
+ */
+fn bson_to_json(value: Bson) -> JsonValue {
+    match value {
+        Bson::ObjectId(oid) => JsonValue::from(oid.to_hex()),
+        Bson::DateTime(datetime) => JsonValue::from(
+            datetime
+                .try_to_rfc3339_string()
+                .unwrap_or(datetime.timestamp_millis().to_string()),
+        ),
+        Bson::Decimal128(decimal) => JsonValue::from(decimal.to_string()),
+        Bson::Binary(binary) => binary_to_json(&binary.bytes),
+        Bson::Int64(v) => JsonValue::from(v),
+        Bson::Symbol(v) => JsonValue::from(v),
+        other => other.into_relaxed_extjson(),
+    }
+}
@@ -1,13 +1,17 @@
-use std::collections::HashMap;
-use std::panic::catch_unwind;
-
-use chrono::{TimeZone, Utc};
+use futures::TryStreamExt;
+use mysql_async::consts::{ColumnFlags, ColumnType};
 use mysql_async::prelude::Queryable;
-use mysql_async::{OptsBuilder, Pool, Row, Value};
+use mysql_async::{Column, Conn, OptsBuilder, Pool, Row, Value};
 
 pub use serde_json::Value as JsonValue;
 
-use super::{Adapter, AdapterOpts, QueryResult};
+use super::cancel::RunningQueries;
+use super::pinned::PinnedConnection;
+use super::{
+    binary_to_json, float_to_json, Adapter, AdapterOpts, QueryColumn, QuerySink, QueryWarning,
+    StatementInfo,
+};
+use crate::error::ErrorKind;
 use crate::AppError;
 
 pub async fn connect(opts: AdapterOpts) -> Result<MySQLAdapter, AppError>
@@ -24,99 +28,448 @@ where
     let pool = Pool::new(mysql_opts);
 
     // attempt a connection in order to validate credentials.
-    pool.get_conn().await.map_err(AppError::from)?;
+    pool.get_conn().await.map_err(AppError::mysql)?;
 
-    Ok(MySQLAdapter { pool: pool })
+    Ok(MySQLAdapter {
+        pool: pool,
+        running: RunningQueries::default(),
+        pinned: None,
+    })
 }
 
 #[derive(Clone)]
 pub struct MySQLAdapter {
     pool: Pool,
+    /// in-flight queries, by the connection id running them
+    running: RunningQueries<u32>,
+    /// queries run on it instead of the pool, once pinned
+    pinned: Option<PinnedConnection<Conn>>,
 }
-impl Adapter for MySQLAdapter {
-    async fn query(
+impl MySQLAdapter {
+    /**
+     * A copy of the adapter keeping one connection of the pool to itself.
+     */
+    pub async fn pin(&self) -> Result<MySQLAdapter, AppError> {
+        let conn = self.pool.get_conn().await.map_err(AppError::mysql)?;
+        Ok(MySQLAdapter {
+            pinned: Some(PinnedConnection::new(conn)),
+            ..self.clone()
+        })
+    }
+
+    pub fn pooled(&self) -> MySQLAdapter {
+        MySQLAdapter {
+            pinned: None,
+            ..self.clone()
+        }
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.pinned.is_some()
+    }
+
+    async fn run<S: QuerySink + Send>(
         &self,
+        conn: &mut Conn,
         query: String,
         database: Option<String>,
-    ) -> Result<QueryResult, AppError> {
-        let mut conn = self.pool.get_conn().await.map_err(AppError::from)?;
-
+        query_id: Option<String>,
+        sink: &mut S,
+    ) -> Result<(), AppError> {
         if let Some(db_name) = database {
             conn.query_drop(format!("USE `{}`", db_name))
                 .await
-                .map_err(AppError::from)?;
+                .map_err(AppError::mysql)?;
         }
 
-        println!("QUERY: {}", query);
-        let start_time = std::time::SystemTime::now();
+        log::debug!("Running query: {}", query);
+        let guard = self.running.start(query_id, conn.id());
+
+        let result: Result<(), AppError> = async {
+            let mut query_result = conn
+                .query_iter(query.as_str())
+                .await
+                .map_err(|why| guard.error(query_error(why, query.as_str())))?;
+
+            // a script (or a stored procedure) yields one result per statement.
+            let mut last_info = loop {
+                // read the column metadata before the rows so empty result sets still report their columns.
+                sink.columns(query_result.columns_ref().iter().map(column_meta).collect())
+                    .await?;
+
+                // rows are handed over as they arrive instead of buffering the whole result set.
+                let has_result_set = match query_result
+                    .stream::<Row>()
+                    .await
+                    .map_err(|why| guard.error(query_error(why, query.as_str())))?
+                {
+                    Some(mut rows) => {
+                        while let Some(row) = rows
+                            .try_next()
+                            .await
+                            .map_err(|why| guard.error(query_error(why, query.as_str())))?
+                        {
+                            sink.row(parse_row(row)).await?;
+                        }
+                        true
+                    }
+                    None => false,
+                };
+
+                // the OK packet closing the result set has been read by now.
+                let info = StatementInfo {
+                    affected_rows: Some(query_result.affected_rows()),
+                    last_insert_id: query_result.last_insert_id(),
+                    warning_count: Some(query_result.warnings() as u64),
+                    ..<_>::default()
+                };
+                if !has_result_set || query_result.is_empty() {
+                    break info;
+                }
+                sink.end_statement(info).await?;
+            };
+
+            query_result
+                .drop_result()
+                .await
+                .map_err(|why| guard.error(query_error(why, query.as_str())))?;
 
-        let query_results: Vec<Row> = conn.query(query.as_str()).await.map_err(AppError::from)?;
-        println!("Retrieved {} results", query_results.len());
-        let mut results: Vec<HashMap<String, JsonValue>> = Vec::new();
+            // the server only keeps the warnings of the last statement around.
+            if last_info.warning_count.unwrap_or(0) > 0 {
+                last_info.warnings = show_warnings(conn).await?;
+            }
+            sink.end_statement(last_info).await
+        }
+        .await;
 
-        for row in query_results {
-            let row_map = parse_row(row)?;
-            results.push(row_map);
+        // a KILL QUERY on its way has to reach the server before the connection runs anything else.
+        guard.finish().await;
+        result
+    }
+}
+impl Adapter for MySQLAdapter {
+    async fn query_stream<S: QuerySink + Send>(
+        &self,
+        query: String,
+        database: Option<String>,
+        query_id: Option<String>,
+        sink: &mut S,
+    ) -> Result<(), AppError> {
+        match &self.pinned {
+            Some(pinned) => {
+                let mut conn = pinned.lock().await?;
+                self.run(&mut conn, query, database, query_id, sink).await
+            }
+            None => {
+                let mut conn = self.pool.get_conn().await.map_err(AppError::mysql)?;
+                self.run(&mut conn, query, database, query_id, sink).await
+            }
         }
+    }
 
-        Ok(QueryResult::make(start_time, Vec::from_iter(results)))
+    /**
+     * KILL QUERY has to be sent over a different connection than the one running the query.
+     */
+    async fn cancel(&self, query_id: String) -> Result<bool, AppError> {
+        let killed = self
+            .running
+            .kill_with(query_id.as_str(), |connection_id| async move {
+                let mut conn = self.pool.get_conn().await.map_err(AppError::mysql)?;
+                conn.query_drop(format!("KILL QUERY {}", connection_id))
+                    .await
+                    .map_err(AppError::mysql)
+            })
+            .await;
+        match killed {
+            Some(killed) => killed.map(|_| true),
+            None => Ok(false),
+        }
     }
 
+    /**
+     * The pool only disconnects once every connection is back, so the pinned one is closed first.
+     */
     async fn disconnect(&mut self) -> Result<bool, AppError> {
+        let pinned = match self.pinned.take() {
+            Some(pinned) => pinned.take().await,
+            None => None,
+        };
+        if let Some(conn) = pinned {
+            if let Err(why) = conn.disconnect().await {
+                log::warn!("Unable to close the pinned connection: {}", why);
+            }
+        }
         match self.to_owned().pool.disconnect().await {
             Ok(_) => Ok(true),
-            Err(why) => Err(AppError::from(why)),
+            Err(why) => Err(AppError::mysql(why)),
         }
     }
 }
 
-fn parse_row(row: Row) -> Result<HashMap<String, JsonValue>, AppError> {
-    let mut map = HashMap::new();
+async fn show_warnings(conn: &mut Conn) -> Result<Vec<QueryWarning>, AppError> {
+    let warnings: Vec<(String, u32, String)> =
+        conn.query("SHOW WARNINGS").await.map_err(AppError::mysql)?;
+    Ok(warnings
+        .into_iter()
+        .map(|(level, code, message)| QueryWarning {
+            level: level,
+            code: Some(code),
+            message: message,
+        })
+        .collect())
+}
+
+/**
+ * MySQL points at syntax errors with the text that follows them, e.g. "near 'FORM albums' at line
+ * 1". Finding that text in the query gives the position postgres would have reported.
+ */
+fn query_error(err: mysql_async::Error, query: &str) -> AppError {
+    let err = AppError::mysql(err);
+    if err.kind != ErrorKind::Syntax {
+        return err;
+    }
+    let near = err
+        .error
+        .split_once("near '")
+        .and_then(|(_, rest)| rest.rsplit_once("' at line"))
+        .map(|(near, _)| near);
+    let offset = match near {
+        Some("") => Some(query.trim_end().len()),
+        Some(near) => query.find(near),
+        None => None,
+    };
+    AppError {
+        position: offset.map(|offset| query[..offset].chars().count() + 1),
+        ..err
+    }
+}
+
+/**
+ * Charset number 63 is "binary", which is how mysql tells BLOB from TEXT and BINARY from CHAR.
+ */
+const BINARY_CHARSET: u16 = 63;
+
+fn column_meta(column: &Column) -> QueryColumn {
+    let flags = column.flags();
+    let is_binary = column.character_set() == BINARY_CHARSET;
+    let is_unsigned = flags.contains(ColumnFlags::UNSIGNED_FLAG);
+
+    let type_name = match column.column_type() {
+        ColumnType::MYSQL_TYPE_DECIMAL | ColumnType::MYSQL_TYPE_NEWDECIMAL => "DECIMAL",
+        ColumnType::MYSQL_TYPE_TINY => "TINYINT",
+        ColumnType::MYSQL_TYPE_SHORT => "SMALLINT",
+        ColumnType::MYSQL_TYPE_INT24 => "MEDIUMINT",
+        ColumnType::MYSQL_TYPE_LONG => "INT",
+        ColumnType::MYSQL_TYPE_LONGLONG => "BIGINT",
+        ColumnType::MYSQL_TYPE_FLOAT => "FLOAT",
+        ColumnType::MYSQL_TYPE_DOUBLE => "DOUBLE",
+        ColumnType::MYSQL_TYPE_NULL => "NULL",
+        ColumnType::MYSQL_TYPE_TIMESTAMP | ColumnType::MYSQL_TYPE_TIMESTAMP2 => "TIMESTAMP",
+        ColumnType::MYSQL_TYPE_DATE | ColumnType::MYSQL_TYPE_NEWDATE => "DATE",
+        ColumnType::MYSQL_TYPE_TIME | ColumnType::MYSQL_TYPE_TIME2 => "TIME",
+        ColumnType::MYSQL_TYPE_DATETIME | ColumnType::MYSQL_TYPE_DATETIME2 => "DATETIME",
+        ColumnType::MYSQL_TYPE_YEAR => "YEAR",
+        ColumnType::MYSQL_TYPE_BIT => "BIT",
+        ColumnType::MYSQL_TYPE_JSON => "JSON",
+        ColumnType::MYSQL_TYPE_GEOMETRY => "GEOMETRY",
+        ColumnType::MYSQL_TYPE_ENUM => "ENUM",
+        ColumnType::MYSQL_TYPE_SET => "SET",
+        // enum and set values are sent as strings, flagged as such
+        ColumnType::MYSQL_TYPE_STRING if flags.contains(ColumnFlags::ENUM_FLAG) => "ENUM",
+        ColumnType::MYSQL_TYPE_STRING if flags.contains(ColumnFlags::SET_FLAG) => "SET",
+        ColumnType::MYSQL_TYPE_STRING if is_binary => "BINARY",
+        ColumnType::MYSQL_TYPE_STRING => "CHAR",
+        ColumnType::MYSQL_TYPE_VARCHAR | ColumnType::MYSQL_TYPE_VAR_STRING if is_binary => {
+            "VARBINARY"
+        }
+        ColumnType::MYSQL_TYPE_VARCHAR | ColumnType::MYSQL_TYPE_VAR_STRING => "VARCHAR",
+        ColumnType::MYSQL_TYPE_TINY_BLOB
+        | ColumnType::MYSQL_TYPE_MEDIUM_BLOB
+        | ColumnType::MYSQL_TYPE_LONG_BLOB
+        | ColumnType::MYSQL_TYPE_BLOB
+            if is_binary =>
+        {
+            "BLOB"
+        }
+        ColumnType::MYSQL_TYPE_TINY_BLOB
+        | ColumnType::MYSQL_TYPE_MEDIUM_BLOB
+        | ColumnType::MYSQL_TYPE_LONG_BLOB
+        | ColumnType::MYSQL_TYPE_BLOB => "TEXT",
+        _ => "UNKNOWN",
+    };
+
+    let is_numeric = matches!(
+        column.column_type(),
+        ColumnType::MYSQL_TYPE_DECIMAL
+            | ColumnType::MYSQL_TYPE_NEWDECIMAL
+            | ColumnType::MYSQL_TYPE_FLOAT
+            | ColumnType::MYSQL_TYPE_DOUBLE
+    );
+    let is_decimal = matches!(
+        column.column_type(),
+        ColumnType::MYSQL_TYPE_DECIMAL | ColumnType::MYSQL_TYPE_NEWDECIMAL
+    );
+
+    // the display length of a DECIMAL includes the sign and the decimal point
+    let precision = if is_decimal {
+        let decimals = column.decimals() as u32;
+        let overhead = (decimals > 0) as u32 + (!is_unsigned) as u32;
+        Some(column.column_length().saturating_sub(overhead))
+    } else {
+        None
+    };
+
+    let table = column.org_table_str();
+
+    QueryColumn {
+        name: column.name_str().to_string(),
+        type_name: if is_unsigned {
+            format!("{} UNSIGNED", type_name)
+        } else {
+            type_name.to_string()
+        },
+        nullable: Some(!flags.contains(ColumnFlags::NOT_NULL_FLAG)),
+        length: Some(column.column_length() as u64),
+        precision: precision,
+        scale: if is_numeric {
+            Some(column.decimals() as u32)
+        } else {
+            None
+        },
+        primary_key: Some(flags.contains(ColumnFlags::PRI_KEY_FLAG)),
+        auto_increment: Some(flags.contains(ColumnFlags::AUTO_INCREMENT_FLAG)),
+        table: if table.is_empty() {
+            None
+        } else {
+            Some(table.to_string())
+        },
+    }
+}
+
+fn parse_row(row: Row) -> Vec<JsonValue> {
     let columns = row.columns();
+    row.unwrap_raw()
+        .into_iter()
+        .zip(columns.iter())
+        .map(|(value, column)| parse_value(value.unwrap_or(Value::NULL), column))
+        .collect()
+}
 
-    for index in 0..columns.len() {
-        let field: String = columns[index].name_str().into();
-
-        let value: JsonValue = match row.get(index).unwrap() {
-            Value::NULL => JsonValue::Null,
-            Value::Bytes(x) => JsonValue::from(unsafe { String::from_utf8_unchecked(x) }),
-            Value::Int(x) => JsonValue::from(x as i64),
-            Value::UInt(x) => JsonValue::from(x as u64),
-            Value::Float(x) => JsonValue::from(x as f32),
-            Value::Double(x) => JsonValue::from(x as f64),
-            Value::Date(year, mon, day, 0u8, 0u8, 0u8, 0u32) => {
-                JsonValue::from(format!("{}-{}-{}", year, mon, day))
-            }
-            Value::Date(year, mon, day, hour, min, sec, _usec) => {
-                // Chrono's LocalResult only implements unwrap with a panic. So we need to catch it.
-                match catch_unwind(|| {
-                    Utc.with_ymd_and_hms(
-                        year as i32,
-                        mon as u32,
-                        day as u32,
-                        hour as u32,
-                        min as u32,
-                        sec as u32,
-                    )
-                    .unwrap()
-                }) {
-                    Ok(datetime) => JsonValue::String(datetime.to_rfc3339()),
-                    Err(_) => JsonValue::from("Invalid DateTime"),
+/**
+ * The text protocol sends every value as bytes, so the column type decides how to read them.
+ * Typed values (from the binary protocol) are handled as well.
+ */
+fn parse_value(value: Value, column: &Column) -> JsonValue {
+    match value {
+        Value::NULL => JsonValue::Null,
+        Value::Bytes(bytes) => parse_bytes(bytes, column),
+        Value::Int(x) => JsonValue::from(x),
+        Value::UInt(x) => JsonValue::from(x),
+        Value::Float(x) => float_to_json(x),
+        Value::Double(x) => JsonValue::from(x),
+        Value::Date(year, mon, day, hour, min, sec, usec) => {
+            let date = format!("{:04}-{:02}-{:02}", year, mon, day);
+            match column.column_type() {
+                ColumnType::MYSQL_TYPE_DATE | ColumnType::MYSQL_TYPE_NEWDATE => {
+                    JsonValue::from(date)
                 }
+                _ => JsonValue::from(format!(
+                    "{} {:02}:{:02}:{:02}{}",
+                    date,
+                    hour,
+                    min,
+                    sec,
+                    format_fraction(usec, column.decimals())
+                )),
             }
-            Value::Time(_neg, _days, hours, mins, secs, _usecs) => {
-                // Chrono's LocalResult only implements unwrap with a panic. So we need to catch it.
-                match catch_unwind(|| {
-                    Utc.with_ymd_and_hms(0, 0, 0, hours as u32, mins as u32, secs as u32)
-                        .unwrap()
-                }) {
-                    Ok(datetime) => JsonValue::String(datetime.format("%T").to_string()),
-                    Err(_) => JsonValue::from("Invalid Time"),
-                }
-            } // _ => JsonValue::from("Unsupported type"),
-        };
-        map.insert(field, value);
+        }
+        Value::Time(is_negative, days, hours, mins, secs, usecs) => {
+            // TIME ranges from -838:59:59 to 838:59:59, so days are folded into the hours.
+            JsonValue::from(format!(
+                "{}{:02}:{:02}:{:02}{}",
+                if is_negative { "-" } else { "" },
+                days * 24 + hours as u32,
+                mins,
+                secs,
+                format_fraction(usecs, column.decimals())
+            ))
+        }
     }
+}
 
-    Ok(map)
+fn parse_bytes(bytes: Vec<u8>, column: &Column) -> JsonValue {
+    let is_unsigned = column.flags().contains(ColumnFlags::UNSIGNED_FLAG);
+
+    match column.column_type() {
+        ColumnType::MYSQL_TYPE_TINY
+        | ColumnType::MYSQL_TYPE_SHORT
+        | ColumnType::MYSQL_TYPE_INT24
+        | ColumnType::MYSQL_TYPE_LONG
+        | ColumnType::MYSQL_TYPE_LONGLONG
+        | ColumnType::MYSQL_TYPE_YEAR => {
+            let text = String::from_utf8_lossy(&bytes);
+            let number = if is_unsigned {
+                text.parse::<u64>().map(JsonValue::from).ok()
+            } else {
+                text.parse::<i64>().map(JsonValue::from).ok()
+            };
+            number.unwrap_or_else(|| JsonValue::from(text.to_string()))
+        }
+        ColumnType::MYSQL_TYPE_FLOAT | ColumnType::MYSQL_TYPE_DOUBLE => {
+            let text = String::from_utf8_lossy(&bytes);
+            match text.parse::<f64>() {
+                Ok(number) if number.is_finite() => JsonValue::from(number),
+                _ => JsonValue::from(text.to_string()),
+            }
+        }
+        // kept as strings: DECIMAL(65, 30) does not fit in a double.
+        ColumnType::MYSQL_TYPE_DECIMAL | ColumnType::MYSQL_TYPE_NEWDECIMAL => {
+            JsonValue::from(String::from_utf8_lossy(&bytes).to_string())
+        }
+        ColumnType::MYSQL_TYPE_BIT if bytes.len() <= 8 => JsonValue::from(
+            bytes
+                .iter()
+                .fold(0u64, |acc, byte| (acc << 8) | *byte as u64),
+        ),
+        ColumnType::MYSQL_TYPE_JSON => match serde_json::from_slice::<JsonValue>(&bytes) {
+            Ok(json) => json,
+            Err(_) => parse_text(bytes),
+        },
+        // SRID followed by WKB
+        ColumnType::MYSQL_TYPE_GEOMETRY => binary_to_json(&bytes),
+        ColumnType::MYSQL_TYPE_STRING
+        | ColumnType::MYSQL_TYPE_VAR_STRING
+        | ColumnType::MYSQL_TYPE_VARCHAR
+        | ColumnType::MYSQL_TYPE_TINY_BLOB
+        | ColumnType::MYSQL_TYPE_MEDIUM_BLOB
+        | ColumnType::MYSQL_TYPE_LONG_BLOB
+        | ColumnType::MYSQL_TYPE_BLOB
+            if column.character_set() == BINARY_CHARSET =>
+        {
+            binary_to_json(&bytes)
+        }
+        _ => parse_text(bytes),
+    }
+}
+
+fn parse_text(bytes: Vec<u8>) -> JsonValue {
+    match String::from_utf8(bytes) {
+        Ok(text) => JsonValue::from(text),
+        Err(why) => binary_to_json(why.as_bytes()),
+    }
+}
+
+/**
+ * Formats microseconds to the column's fractional seconds precision, e.g. DATETIME(3) -> ".123"
+ */
+fn format_fraction(usecs: u32, decimals: u8) -> String {
+    let digits = (decimals as usize).min(6);
+    if digits == 0 {
+        if usecs == 0 {
+            return String::new();
+        }
+        // the precision is unknown (e.g. an expression), so print all of it.
+        return format!(".{:06}", usecs);
+    }
+    format!(".{}", &format!("{:06}", usecs)[..digits])
 }
@@ -0,0 +1,49 @@
+use std::sync::Arc;
+
+use tokio::sync::{MappedMutexGuard, Mutex as AsyncMutex, MutexGuard};
+
+use crate::error::ErrorKind;
+use crate::AppError;
+
+/**
+ * The one physical connection of a pinned session. Whatever a query leaves on it (variables,
+ * temporary tables, locks...) is still there for the next one. Clones share the connection, and a
+ * query holds it until it is done, so the queries of a pinned session run one after the other.
+ */
+pub struct PinnedConnection<C> {
+    conn: Arc<AsyncMutex<Option<C>>>,
+}
+impl<C> Clone for PinnedConnection<C> {
+    fn clone(&self) -> Self {
+        PinnedConnection {
+            conn: self.conn.clone(),
+        }
+    }
+}
+
+impl<C> PinnedConnection<C> {
+    pub fn new(conn: C) -> PinnedConnection<C> {
+        PinnedConnection {
+            conn: Arc::new(AsyncMutex::new(Some(conn))),
+        }
+    }
+
+    /**
+     * Waits for the query ahead (if any) to be done with the connection.
+     */
+    pub async fn lock(&self) -> Result<MappedMutexGuard<'_, C>, AppError> {
+        MutexGuard::try_map(self.conn.lock().await, |conn| conn.as_mut()).map_err(|_| {
+            AppError::new(
+                ErrorKind::NotConnected,
+                "The session's connection was closed!",
+            )
+        })
+    }
+
+    /**
+     * Takes the connection out for the adapter to close it. Later queries fail as not connected.
+     */
+    pub async fn take(&self) -> Option<C> {
+        self.conn.lock().await.take()
+    }
+}
@@ -0,0 +1,412 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use futures::TryStreamExt;
+pub use serde_json::Value as JsonValue;
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::types::Oid;
+use sqlx::postgres::{PgColumn, PgConnectOptions, PgConnection, PgPool, PgPoolOptions, PgRow};
+use sqlx::types::{BigDecimal, Uuid};
+use sqlx::{Column, Decode, Either, Executor, Postgres, Row, Type, TypeInfo, ValueRef};
+
+use super::cancel::RunningQueries;
+use super::pinned::PinnedConnection;
+use super::{
+    binary_to_json, float_to_json, Adapter, AdapterOpts, QueryColumn, QuerySink, StatementInfo,
+};
+use crate::AppError;
+
+pub async fn connect(opts: AdapterOpts) -> Result<PostgresAdapter, AppError>
+where
+    PostgresAdapter: Sized,
+{
+    let pg_opts = PgConnectOptions::new()
+        .host(opts.host.as_str())
+        .port(opts.port as u16)
+        .username(opts.user.as_str())
+        .password(opts.password.as_str());
+
+    // without an explicit database, postgres falls back to a database named after the user.
+    let pg_opts = match &opts.database {
+        Some(database) if !database.is_empty() => pg_opts.database(database),
+        _ => pg_opts,
+    };
+
+    let pool = PgPoolOptions::new()
+        .connect_with(pg_opts)
+        .await
+        .map_err(AppError::sqlx)?;
+
+    // test the pool connection
+    sqlx::query("SELECT 1")
+        .fetch_one(&pool)
+        .await
+        .map_err(AppError::sqlx)?;
+
+    Ok(PostgresAdapter {
+        pool: pool,
+        running: RunningQueries::default(),
+        pinned: None,
+        pinned_pid: None,
+    })
+}
+
+#[derive(Clone)]
+pub struct PostgresAdapter {
+    pool: PgPool,
+    /// in-flight queries, by the backend pid running them
+    running: RunningQueries<i32>,
+    /// queries run on it instead of the pool, once pinned
+    pinned: Option<PinnedConnection<PoolConnection<Postgres>>>,
+    /// the backend pid of the pinned connection
+    pinned_pid: Option<i32>,
+}
+impl PostgresAdapter {
+    /**
+     * A copy of the adapter keeping one connection of the pool to itself.
+     */
+    pub async fn pin(&self) -> Result<PostgresAdapter, AppError> {
+        let mut conn = self.pool.acquire().await.map_err(AppError::sqlx)?;
+        let backend_pid: i32 = sqlx::query_scalar("SELECT pg_backend_pid()")
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(AppError::sqlx)?;
+        Ok(PostgresAdapter {
+            pinned: Some(PinnedConnection::new(conn)),
+            pinned_pid: Some(backend_pid),
+            ..self.clone()
+        })
+    }
+
+    pub fn pooled(&self) -> PostgresAdapter {
+        PostgresAdapter {
+            pinned: None,
+            pinned_pid: None,
+            ..self.clone()
+        }
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.pinned.is_some()
+    }
+
+    async fn run<S: QuerySink + Send>(
+        &self,
+        conn: &mut PgConnection,
+        query: String,
+        database: Option<String>,
+        query_id: Option<String>,
+        sink: &mut S,
+    ) -> Result<(), AppError> {
+        // the pid is only needed to cancel the query. A pooled connection's is read for every
+        // query that can be cancelled, as the next one may get another connection.
+        let backend_pid: i32 = match (self.pinned_pid, &query_id) {
+            (Some(backend_pid), _) => backend_pid,
+            (None, Some(_)) => sqlx::query_scalar("SELECT pg_backend_pid()")
+                .fetch_one(&mut *conn)
+                .await
+                .map_err(AppError::sqlx)?,
+            (None, None) => 0,
+        };
+
+        // a pooled connection still has the search_path of the query it last ran, put it back
+        // unless one is given. A pinned session keeps whatever it set.
+        let search_path = match database {
+            Some(schema) => Some(format!("SET search_path TO {}", quote_identifier(&schema))),
+            None if !self.is_pinned() => Some("RESET search_path".to_string()),
+            None => None,
+        };
+        if let Some(search_path) = search_path {
+            conn.execute(search_path.as_str())
+                .await
+                .map_err(AppError::sqlx)?;
+        }
+
+        let guard = self.running.start(query_id, backend_pid);
+        let streamed: Result<Option<StatementInfo>, AppError> = async {
+            let mut has_columns = false;
+            let mut num_statements = 0;
+            // a lone statement without rows is described once the connection is free again.
+            let mut undescribed: Option<StatementInfo> = None;
+
+            // every statement's rows are followed by its CommandComplete.
+            let mut results = sqlx::raw_sql(query.as_str()).fetch_many(&mut *conn);
+            while let Some(result) = results
+                .try_next()
+                .await
+                .map_err(|why| guard.error(AppError::sqlx(why)))?
+            {
+                if let Some(info) = undescribed.take() {
+                    sink.columns(Vec::new()).await?;
+                    sink.end_statement(info).await?;
+                }
+                match result {
+                    Either::Right(row) => {
+                        if !has_columns {
+                            sink.columns(self.columns_meta(row.columns()).await).await?;
+                            has_columns = true;
+                        }
+                        sink.row(parse_row(&row)).await?;
+                    }
+                    Either::Left(done) => {
+                        let info = StatementInfo {
+                            affected_rows: Some(done.rows_affected()),
+                            ..<_>::default()
+                        };
+                        match (has_columns, num_statements) {
+                            (false, 0) => undescribed = Some(info),
+                            (false, _) => {
+                                sink.columns(Vec::new()).await?;
+                                sink.end_statement(info).await?;
+                            }
+                            (true, _) => sink.end_statement(info).await?,
+                        };
+                        has_columns = false;
+                        num_statements += 1;
+                    }
+                }
+            }
+            Ok(undescribed)
+        }
+        .await;
+
+        // a cancel on its way has to reach the server before the connection runs anything else.
+        guard.finish().await;
+
+        if let Some(info) = streamed? {
+            // prepare the statement in order to report the columns of an empty result.
+            let columns = match (&mut *conn).describe(query.as_str()).await {
+                Ok(describe) => self
+                    .columns_meta(describe.columns())
+                    .await
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, column)| QueryColumn {
+                        nullable: describe.nullable(i).or(column.nullable),
+                        ..column
+                    })
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+            sink.columns(columns).await?;
+            sink.end_statement(info).await?;
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Postgres only tells which table column a result column was read from by their oid and
+     * attribute number, so look the table's name and the column's NOT NULL up in the catalog. A
+     * NOT NULL column can still come out null through an outer join, only describe knows that.
+     * Expressions have no source and keep their nullability unknown.
+     */
+    async fn columns_meta(&self, columns: &[PgColumn]) -> Vec<QueryColumn> {
+        let mut meta: Vec<QueryColumn> = columns.iter().map(column_meta).collect();
+        let (relation_ids, attribute_nos): (Vec<Oid>, Vec<i16>) = columns
+            .iter()
+            .filter_map(|column| Some((column.relation_id()?, column.relation_attribute_no()?)))
+            .unzip();
+        if relation_ids.is_empty() {
+            return meta;
+        }
+
+        // the session's own connection is busy streaming the rows.
+        let sources: Vec<(Oid, i16, String, bool)> = match sqlx::query_as(
+            "SELECT a.attrelid, a.attnum, c.relname::text, a.attnotnull FROM pg_attribute a \
+                JOIN pg_class c ON c.oid = a.attrelid \
+                WHERE (a.attrelid, a.attnum) IN (SELECT * FROM UNNEST($1::oid[], $2::int2[]))",
+        )
+        .bind(relation_ids)
+        .bind(attribute_nos)
+        .fetch_all(&self.pool)
+        .await
+        {
+            Ok(sources) => sources,
+            Err(_) => return meta,
+        };
+
+        for (column, meta) in columns.iter().zip(meta.iter_mut()) {
+            let source = sources.iter().find(|(relation_id, attribute_no, _, _)| {
+                Some(*relation_id) == column.relation_id()
+                    && Some(*attribute_no) == column.relation_attribute_no()
+            });
+            if let Some((_, _, table, not_null)) = source {
+                meta.table = Some(table.clone());
+                meta.nullable = Some(!not_null);
+            }
+        }
+        meta
+    }
+}
+impl Adapter for PostgresAdapter {
+    /**
+     * For postgres, the "database" is the schema placed at the front of the search_path.
+     */
+    async fn query_stream<S: QuerySink + Send>(
+        &self,
+        query: String,
+        database: Option<String>,
+        query_id: Option<String>,
+        sink: &mut S,
+    ) -> Result<(), AppError> {
+        match &self.pinned {
+            Some(pinned) => {
+                let mut conn = pinned.lock().await?;
+                self.run(&mut conn, query, database, query_id, sink).await
+            }
+            None => {
+                let mut conn = self.pool.acquire().await.map_err(AppError::sqlx)?;
+                self.run(&mut conn, query, database, query_id, sink).await
+            }
+        }
+    }
+
+    async fn cancel(&self, query_id: String) -> Result<bool, AppError> {
+        let cancelled = self
+            .running
+            .kill_with(query_id.as_str(), |backend_pid| async move {
+                sqlx::query("SELECT pg_cancel_backend($1)")
+                    .bind(backend_pid)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(AppError::sqlx)
+            })
+            .await;
+        match cancelled {
+            Some(cancelled) => cancelled.map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /**
+     * Closing the pool waits for every connection to be back, so the pinned one is closed first.
+     */
+    async fn disconnect(&mut self) -> Result<bool, AppError> {
+        let pinned = match self.pinned.take() {
+            Some(pinned) => pinned.take().await,
+            None => None,
+        };
+        if let Some(conn) = pinned {
+            if let Err(why) = conn.close().await {
+                log::warn!("Unable to close the pinned connection: {}", why);
+            }
+        }
+        self.pool.close().await;
+        Ok(true)
+    }
+}
+
+fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn column_meta(column: &PgColumn) -> QueryColumn {
+    QueryColumn::new(
+        column.name().to_string(),
+        column.type_info().name().to_string(),
+    )
+}
+
+fn parse_row(row: &PgRow) -> Vec<JsonValue> {
+    row.columns()
+        .iter()
+        .map(|column| parse_value(row, column.ordinal(), column.type_info().name()))
+        .collect()
+}
+
+/**
+ * Decodes a single value based on its postgres type name. Anything we don't have a dedicated
+ * decoder for (or that fails to decode, e.g. NaN numerics or multi-dimensional arrays) falls back
+ * to the text postgres itself would print, so no value is ever dropped.
+ */
+fn parse_value(row: &PgRow, index: usize, type_name: &str) -> JsonValue {
+    match row.try_get_raw(index) {
+        Ok(value) if value.is_null() => return JsonValue::Null,
+        Err(_) => return JsonValue::Null,
+        _ => (),
+    };
+
+    let decoded: Result<JsonValue, sqlx::Error> = match type_name {
+        "BOOL" => row.try_get::<bool, _>(index).map(JsonValue::from),
+        "INT2" => row.try_get::<i16, _>(index).map(JsonValue::from),
+        "INT4" => row.try_get::<i32, _>(index).map(JsonValue::from),
+        "INT8" => row.try_get::<i64, _>(index).map(JsonValue::from),
+        "OID" => row
+            .try_get::<Oid, _>(index)
+            .map(|oid| JsonValue::from(oid.0)),
+        "FLOAT4" => row.try_get::<f32, _>(index).map(float_to_json),
+        "FLOAT8" => row.try_get::<f64, _>(index).map(JsonValue::from),
+        "NUMERIC" => row
+            .try_get::<BigDecimal, _>(index)
+            .map(|v| JsonValue::from(v.to_string())),
+        "JSON" | "JSONB" => row.try_get::<JsonValue, _>(index),
+        "UUID" => row
+            .try_get::<Uuid, _>(index)
+            .map(|v| JsonValue::from(v.to_string())),
+        "TIMESTAMPTZ" => row
+            .try_get::<DateTime<Utc>, _>(index)
+            .map(|v| JsonValue::from(v.to_rfc3339())),
+        "TIMESTAMP" => row
+            .try_get::<NaiveDateTime, _>(index)
+            .map(|v| JsonValue::from(v.format("%Y-%m-%d %H:%M:%S%.f").to_string())),
+        "DATE" => row
+            .try_get::<NaiveDate, _>(index)
+            .map(|v| JsonValue::from(v.to_string())),
+        "TIME" => row
+            .try_get::<NaiveTime, _>(index)
+            .map(|v| JsonValue::from(v.format("%H:%M:%S%.f").to_string())),
+        "BYTEA" => row.try_get::<Vec<u8>, _>(index).map(|v| binary_to_json(&v)),
+        "BOOL[]" => parse_array::<bool>(row, index, JsonValue::from),
+        "INT2[]" => parse_array::<i16>(row, index, JsonValue::from),
+        "INT4[]" => parse_array::<i32>(row, index, JsonValue::from),
+        "INT8[]" => parse_array::<i64>(row, index, JsonValue::from),
+        "FLOAT4[]" => parse_array::<f32>(row, index, float_to_json),
+        "FLOAT8[]" => parse_array::<f64>(row, index, JsonValue::from),
+        "NUMERIC[]" => parse_array::<BigDecimal>(row, index, |v| JsonValue::from(v.to_string())),
+        "TEXT[]" | "VARCHAR[]" | "BPCHAR[]" | "NAME[]" => {
+            parse_array::<String>(row, index, JsonValue::from)
+        }
+        "JSON[]" | "JSONB[]" => parse_array::<JsonValue>(row, index, |v| v),
+        "UUID[]" => parse_array::<Uuid>(row, index, |v| JsonValue::from(v.to_string())),
+        "TIMESTAMPTZ[]" => {
+            parse_array::<DateTime<Utc>>(row, index, |v| JsonValue::from(v.to_rfc3339()))
+        }
+        "DATE[]" => parse_array::<NaiveDate>(row, index, |v| JsonValue::from(v.to_string())),
+        _ => Err(sqlx::Error::Decode(
+            format!("no dedicated decoder for {}", type_name).into(),
+        )),
+    };
+
+    decoded.unwrap_or_else(|_| parse_text(row, index))
+}
+
+fn parse_array<'r, T>(
+    row: &'r PgRow,
+    index: usize,
+    to_json: fn(T) -> JsonValue,
+) -> Result<JsonValue, sqlx::Error>
+where
+    Vec<Option<T>>: Decode<'r, Postgres> + Type<Postgres>,
+{
+    let values: Vec<Option<T>> = row.try_get(index)?;
+    Ok(JsonValue::Array(
+        values
+            .into_iter()
+            .map(|value| value.map(to_json).unwrap_or(JsonValue::Null))
+            .collect(),
+    ))
+}
+
+/**
+ * Returns the raw representation of the value. Queries run over the simple protocol, so this is
+ * the server's text output. Non-utf8 payloads are kept as binary.
+ */
+fn parse_text(row: &PgRow, index: usize) -> JsonValue {
+    let bytes = match row.try_get_raw(index).map(|value| value.as_bytes()) {
+        Ok(Ok(bytes)) => bytes,
+        _ => return JsonValue::Null,
+    };
+    match std::str::from_utf8(bytes) {
+        Ok(text) => JsonValue::from(text),
+        Err(_) => binary_to_json(bytes),
+    }
+}
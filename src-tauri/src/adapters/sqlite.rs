@@ -1,88 +1,269 @@
-use std::collections::HashMap;
+use std::ptr::NonNull;
 
+use futures::TryStreamExt;
+use libsqlite3_sys::{sqlite3, sqlite3_interrupt};
 pub use serde_json::Value as JsonValue;
-use sqlx::sqlite::SqlitePool;
-use sqlx::{Column, Row, TypeInfo};
+use sqlx::pool::PoolConnection;
+use sqlx::sqlite::{SqliteColumn, SqliteConnection, SqlitePool, SqliteRow};
+use sqlx::{Column, Either, Executor, Row, Sqlite, TypeInfo, ValueRef};
 
-use super::{Adapter, AdapterOpts, QueryResult};
+use super::cancel::RunningQueries;
+use super::pinned::PinnedConnection;
+use super::{binary_to_json, Adapter, AdapterOpts, QueryColumn, QuerySink, StatementInfo};
 use crate::AppError;
 
 pub async fn connect(opts: AdapterOpts) -> Result<SQLiteAdapter, AppError>
 where
     SQLiteAdapter: Sized,
 {
-    let pool = SqlitePool::connect(&opts.filepath.as_str())
+    let pool = SqlitePool::connect(opts.filepath.as_str())
         .await
-        .map_err(AppError::from)?;
+        .map_err(AppError::sqlx)?;
 
-    let _conn = pool.acquire().await.map_err(AppError::from)?;
+    let _conn = pool.acquire().await.map_err(AppError::sqlx)?;
 
     // test the pool connection
     sqlx::query("SELECT name FROM sqlite_master LIMIT 1")
         .fetch_one(&pool)
         .await
-        .map_err(AppError::from)?;
+        .map_err(AppError::sqlx)?;
 
-    Ok(SQLiteAdapter { pool: pool })
+    Ok(SQLiteAdapter {
+        pool: pool,
+        running: RunningQueries::default(),
+        pinned: None,
+    })
 }
 
+/**
+ * The raw handle of the connection running a query. sqlite3_interrupt is safe to call from any
+ * thread while the connection is open. The query holds the connection for as long as it is
+ * registered, so the handle is only used through RunningQueries::cancel_with, while the registry
+ * is locked.
+ */
+#[derive(Clone, Copy)]
+struct InterruptHandle(NonNull<sqlite3>);
+unsafe impl Send for InterruptHandle {}
+unsafe impl Sync for InterruptHandle {}
+
 #[derive(Clone)]
 pub struct SQLiteAdapter {
     pool: SqlitePool,
+    running: RunningQueries<InterruptHandle>,
+    /// queries run on it instead of the pool, once pinned
+    pinned: Option<PinnedConnection<PoolConnection<Sqlite>>>,
 }
 impl Adapter for SQLiteAdapter {
     async fn disconnect(&mut self) -> Result<bool, AppError> {
+        let pinned = match self.pinned.take() {
+            Some(pinned) => pinned.take().await,
+            None => None,
+        };
+        if let Some(conn) = pinned {
+            if let Err(why) = conn.close().await {
+                log::warn!("Unable to close the pinned connection: {}", why);
+            }
+        }
         Ok(true)
     }
 
-    async fn query(
+    async fn query_stream<S: QuerySink + Send>(
         &self,
         query: String,
         _database: Option<String>,
-    ) -> Result<QueryResult, AppError> {
-        let start_time = std::time::SystemTime::now();
+        query_id: Option<String>,
+        sink: &mut S,
+    ) -> Result<(), AppError> {
+        match &self.pinned {
+            Some(pinned) => {
+                let mut conn = pinned.lock().await?;
+                self.run(&mut conn, query, query_id, sink).await
+            }
+            None => {
+                let mut conn = self.pool.acquire().await.map_err(AppError::sqlx)?;
+                self.run(&mut conn, query, query_id, sink).await
+            }
+        }
+    }
 
-        let query_results = sqlx::raw_sql(&query.as_str())
-            .fetch_all(&self.pool)
-            .await
-            .map_err(AppError::from)?;
+    async fn cancel(&self, query_id: String) -> Result<bool, AppError> {
+        let interrupted = self
+            .running
+            .cancel_with(query_id.as_str(), |handle| unsafe {
+                sqlite3_interrupt(handle.0.as_ptr())
+            });
+        Ok(interrupted.is_some())
+    }
+}
+impl SQLiteAdapter {
+    /**
+     * A copy of the adapter keeping one connection of the pool to itself, e.g. for its temporary
+     * tables and attached databases.
+     */
+    pub async fn pin(&self) -> Result<SQLiteAdapter, AppError> {
+        let conn = self.pool.acquire().await.map_err(AppError::sqlx)?;
+        Ok(SQLiteAdapter {
+            pinned: Some(PinnedConnection::new(conn)),
+            ..self.clone()
+        })
+    }
 
-        let mut results: Vec<HashMap<String, JsonValue>> = Vec::with_capacity(query_results.len());
+    pub fn pooled(&self) -> SQLiteAdapter {
+        SQLiteAdapter {
+            pinned: None,
+            ..self.clone()
+        }
+    }
 
-        for row in query_results {
-            let mut map = HashMap::new();
-            for column in row.columns() {
-                let i = column.ordinal();
+    pub fn is_pinned(&self) -> bool {
+        self.pinned.is_some()
+    }
 
-                let value = match column.type_info().name() {
-                    "NULL" => JsonValue::Null,
-                    "INTEGER" => {
-                        let v: i32 = row.get(&i);
-                        JsonValue::from(v)
-                    }
-                    "TEXT" => {
-                        let v: String = row.get(&i);
-                        JsonValue::from(v)
-                    }
-                    "REAL" => {
-                        let v: f64 = row.get(&i);
-                        JsonValue::from(v)
-                    }
-                    "DATETIME" => {
-                        let v: String = row.get(&i);
-                        JsonValue::from(v)
+    async fn run<S: QuerySink + Send>(
+        &self,
+        conn: &mut SqliteConnection,
+        query: String,
+        query_id: Option<String>,
+        sink: &mut S,
+    ) -> Result<(), AppError> {
+        let handle = InterruptHandle(
+            conn.lock_handle()
+                .await
+                .map_err(AppError::sqlx)?
+                .as_raw_handle(),
+        );
+
+        // only the first statement can be described, for whether its columns can be null. Rows
+        // carry their own columns, so it also tells the columns of an empty result.
+        let described: Vec<QueryColumn> = match (&mut *conn).describe(query.as_str()).await {
+            Ok(describe) => describe
+                .columns()
+                .iter()
+                .enumerate()
+                .map(|(i, column)| QueryColumn {
+                    nullable: describe.nullable(i),
+                    ..column_meta(column)
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        let mut last_insert_rowid: i64 = sqlx::query_scalar("SELECT last_insert_rowid()")
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(AppError::sqlx)?;
+
+        let guard = self.running.start(query_id, handle);
+        let mut has_columns = false;
+        let mut num_statements = 0;
+
+        // every statement's rows are followed by its summary.
+        let mut results = sqlx::raw_sql(query.as_str()).fetch_many(&mut *conn);
+        while let Some(result) = results
+            .try_next()
+            .await
+            .map_err(|why| guard.error(AppError::sqlx(why)))?
+        {
+            match result {
+                Either::Right(row) => {
+                    if !has_columns {
+                        let mut columns: Vec<QueryColumn> =
+                            row.columns().iter().map(column_meta).collect();
+                        if num_statements == 0 && described.len() == columns.len() {
+                            for (column, described) in columns.iter_mut().zip(&described) {
+                                column.nullable = described.nullable;
+                            }
+                        }
+                        sink.columns(columns).await?;
+                        has_columns = true;
                     }
-                    _ => {
-                        println!("Unable to decode '{}'", column.type_info().name());
-                        JsonValue::Null
+                    sink.row(parse_row(&row)?).await?;
+                }
+                Either::Left(done) => {
+                    if !has_columns {
+                        let columns = match num_statements {
+                            0 => described.clone(),
+                            _ => Vec::new(),
+                        };
+                        sink.columns(columns).await?;
                     }
-                };
-
-                map.insert(column.name().to_string(), value);
+                    // last_insert_rowid is per connection and outlives the INSERT that set it, so
+                    // it only belongs to a statement that changed it.
+                    let inserted = done.last_insert_rowid();
+                    sink.end_statement(StatementInfo {
+                        affected_rows: Some(done.rows_affected()),
+                        last_insert_id: match inserted == last_insert_rowid {
+                            true => None,
+                            false => Some(inserted as u64),
+                        },
+                        ..<_>::default()
+                    })
+                    .await?;
+                    last_insert_rowid = inserted;
+                    has_columns = false;
+                    num_statements += 1;
+                }
             }
-            results.push(map);
         }
 
-        Ok(QueryResult::make(start_time, Vec::from_iter(results)))
+        Ok(())
+    }
+}
+
+/**
+ * The declared type of the column, e.g. "INTEGER" or "DATETIME". Expression columns have no
+ * declared type, in which case sqlite reports the storage class of the first value. The table a
+ * column was read from stays unknown, sqlx doesn't expose sqlite3_column_table_name.
+ */
+fn column_meta(column: &SqliteColumn) -> QueryColumn {
+    QueryColumn::new(
+        column.name().to_string(),
+        column.type_info().name().to_string(),
+    )
+}
+
+fn parse_row(row: &SqliteRow) -> Result<Vec<JsonValue>, AppError> {
+    let mut values = Vec::with_capacity(row.len());
+    for index in 0..row.len() {
+        values.push(parse_value(row, index)?);
     }
+    Ok(values)
+}
+
+/**
+ * Sqlite columns don't constrain their values, so the declared type (NUMERIC, BOOLEAN,
+ * VARCHAR(10), or none at all for expressions) says little about what is stored. Decode each value
+ * by its own storage class instead.
+ */
+fn parse_value(row: &SqliteRow, index: usize) -> Result<JsonValue, AppError> {
+    let raw = row.try_get_raw(index).map_err(AppError::sqlx)?;
+    if raw.is_null() {
+        return Ok(JsonValue::Null);
+    }
+    let storage_class = raw.type_info().name().to_string();
+
+    let value = match storage_class.as_str() {
+        "INTEGER" => JsonValue::from(
+            row.try_get_unchecked::<i64, _>(index)
+                .map_err(AppError::sqlx)?,
+        ),
+        "REAL" => JsonValue::from(
+            row.try_get_unchecked::<f64, _>(index)
+                .map_err(AppError::sqlx)?,
+        ),
+        "TEXT" => match row.try_get_unchecked::<String, _>(index) {
+            Ok(text) => JsonValue::from(text),
+            // sqlite doesn't enforce encodings, so TEXT may hold invalid utf-8. keep the bytes.
+            Err(_) => binary_to_json(
+                &row.try_get_unchecked::<Vec<u8>, _>(index)
+                    .map_err(AppError::sqlx)?,
+            ),
+        },
+        _ => binary_to_json(
+            &row.try_get_unchecked::<Vec<u8>, _>(index)
+                .map_err(AppError::sqlx)?,
+        ),
+    };
+
+    Ok(value)
 }
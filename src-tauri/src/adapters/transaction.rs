@@ -0,0 +1,232 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::error::ErrorKind;
+use crate::AppError;
+
+/**
+ * Called once a transaction was rolled back for being idle too long, with what it was like.
+ */
+pub type AutoRollbackListener = Arc<dyn Fn(TransactionInfo) + Send + Sync>;
+
+/**
+ * The state of a pinned session's transaction, as reported to the frontend.
+ */
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct TransactionInfo {
+    pub open: bool,
+    pub started_at: Option<DateTime<Local>>,
+    /// seconds since the last query of the transaction ended, 0 while one runs
+    pub idle_secs: Option<u64>,
+    /// oldest first
+    pub savepoints: Vec<String>,
+    /// the transaction is rolled back once idle for that long
+    pub auto_rollback_secs: Option<f64>,
+}
+
+struct OpenTransaction {
+    id: String,
+    started_at: DateTime<Local>,
+    last_used: Instant,
+    /// queries running in the transaction, which is never idle meanwhile
+    running: usize,
+    savepoints: Vec<String>,
+    auto_rollback: Option<Duration>,
+}
+impl OpenTransaction {
+    fn info(&self) -> TransactionInfo {
+        TransactionInfo {
+            open: true,
+            started_at: Some(self.started_at),
+            idle_secs: Some(match self.running {
+                0 => self.last_used.elapsed().as_secs(),
+                _ => 0,
+            }),
+            savepoints: self.savepoints.clone(),
+            auto_rollback_secs: self.auto_rollback.map(|timeout| timeout.as_secs_f64()),
+        }
+    }
+
+    fn idle(&self) -> Idle {
+        match (self.auto_rollback, self.running) {
+            (None, _) => Idle::Ended,
+            (Some(timeout), 0) => match timeout.checked_sub(self.last_used.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => Idle::Wait(remaining),
+                _ => Idle::Expired,
+            },
+            (Some(timeout), _) => Idle::Wait(timeout),
+        }
+    }
+}
+
+/**
+ * What the idle watch should do next.
+ */
+pub enum Idle {
+    /// the transaction was committed or rolled back, or another one began
+    Ended,
+    Wait(Duration),
+    Expired,
+}
+
+/**
+ * Tracks the transaction of a pinned session, as opened and closed through Connection::begin,
+ * commit and rollback. Transaction statements run as plain queries aren't tracked. Clones share
+ * the state.
+ */
+#[derive(Clone, Default)]
+pub struct Transaction {
+    open: Arc<Mutex<Option<OpenTransaction>>>,
+}
+impl Transaction {
+    /**
+     * Every change is a single assignment, so a poisoned lock is used as is.
+     */
+    fn lock(&self) -> MutexGuard<'_, Option<OpenTransaction>> {
+        self.open.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.lock().is_some()
+    }
+
+    pub fn info(&self) -> TransactionInfo {
+        match self.lock().as_ref() {
+            Some(open) => open.info(),
+            None => TransactionInfo::default(),
+        }
+    }
+
+    /**
+     * Marks the transaction as open, returning its id. Fails if one already is.
+     */
+    pub fn open(&self, auto_rollback: Option<Duration>) -> Result<String, AppError> {
+        let mut open = self.lock();
+        if open.is_some() {
+            return Err(AppError::new(
+                ErrorKind::Transaction,
+                "A transaction is already open, commit or roll it back first!",
+            ));
+        }
+        let id = crate::uuidv4!();
+        *open = Some(OpenTransaction {
+            id: id.clone(),
+            started_at: Local::now(),
+            last_used: Instant::now(),
+            running: 0,
+            savepoints: Vec::new(),
+            auto_rollback: auto_rollback,
+        });
+        Ok(id)
+    }
+
+    pub fn close(&self) {
+        self.lock().take();
+    }
+
+    /**
+     * Takes the transaction with that id over from its session if it is still expired, returning
+     * what it was like. Whatever the session does with it afterwards fails as if it was closed.
+     */
+    pub fn take_if_expired(&self, id: &str) -> Option<TransactionInfo> {
+        let mut open = self.lock();
+        match open.as_ref().map(|open| (open.id == id, open.idle())) {
+            Some((true, Idle::Expired)) => open.take().map(|open| open.info()),
+            _ => None,
+        }
+    }
+
+    /**
+     * Counts a transaction statement (e.g. COMMIT) as running in the open transaction, which
+     * keeps it from being rolled back for being idle meanwhile.
+     */
+    pub fn enter(&self) -> Result<Activity, AppError> {
+        let activity = self.activity();
+        match activity.id {
+            Some(_) => Ok(activity),
+            None => Err(AppError::new(
+                ErrorKind::Transaction,
+                "No transaction is open!",
+            )),
+        }
+    }
+
+    pub fn has_savepoint(&self, name: &str) -> bool {
+        self.lock()
+            .as_ref()
+            .is_some_and(|open| open.savepoints.iter().any(|savepoint| savepoint == name))
+    }
+
+    /**
+     * A savepoint set again under the same name moves, as it does on the server.
+     */
+    pub fn add_savepoint(&self, name: &str) {
+        if let Some(open) = self.lock().as_mut() {
+            open.savepoints.retain(|savepoint| savepoint != name);
+            open.savepoints.push(name.to_string());
+        }
+    }
+
+    /**
+     * Rolling back to a savepoint drops the ones set after it, and keeps it.
+     */
+    pub fn rolled_back_to(&self, name: &str) {
+        if let Some(open) = self.lock().as_mut() {
+            if let Some(index) = open
+                .savepoints
+                .iter()
+                .position(|savepoint| savepoint == name)
+            {
+                open.savepoints.truncate(index + 1);
+            }
+        }
+    }
+
+    /**
+     * Counts a query as running in the transaction until the returned guard is dropped.
+     */
+    pub fn activity(&self) -> Activity {
+        let id = self.lock().as_mut().map(|open| {
+            open.running += 1;
+            open.id.clone()
+        });
+        Activity {
+            transaction: self.clone(),
+            id: id,
+        }
+    }
+
+    /**
+     * How long the transaction with that id can stay idle before it is rolled back.
+     */
+    pub fn idle(&self, id: &str) -> Idle {
+        match self.lock().as_ref() {
+            Some(open) if open.id == id => open.idle(),
+            _ => Idle::Ended,
+        }
+    }
+}
+
+/**
+ * A query running in the transaction. The transaction is idle from the moment it is dropped, be
+ * it because the query ended or because it was stopped, e.g. along with its cursor.
+ */
+pub struct Activity {
+    transaction: Transaction,
+    /// the transaction the query was counted in, if one was open
+    id: Option<String>,
+}
+impl Drop for Activity {
+    fn drop(&mut self) {
+        match self.transaction.lock().as_mut() {
+            Some(open) if Some(&open.id) == self.id.as_ref() => {
+                open.running -= 1;
+                open.last_used = Instant::now();
+            }
+            _ => (),
+        }
+    }
+}
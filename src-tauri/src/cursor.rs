@@ -0,0 +1,270 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use tokio::sync::{mpsc, Mutex as AsyncMutex};
+use tokio::task::JoinHandle;
+
+use crate::adapters::{
+    Adapter, Connection, JsonValue, QueryColumn, QueryResult, QuerySink, StatementInfo,
+};
+use crate::AppError;
+
+/// rows per page when the frontend doesn't ask for a size
+pub const DEFAULT_PAGE_SIZE: usize = 1000;
+
+/// every open cursor holds on to a connection, so the oldest ones are closed past this count
+const MAX_OPEN_CURSORS: usize = 8;
+
+enum CursorEvent {
+    Columns {
+        columns: Vec<QueryColumn>,
+        total_rows: Option<usize>,
+    },
+    Row(Vec<JsonValue>),
+    End(StatementInfo),
+}
+
+/**
+ * Hands the rows over to the cursor one at a time. The channel only holds a single row, so the
+ * driver stops reading until the next page is fetched.
+ */
+struct PageSink {
+    events: mpsc::Sender<CursorEvent>,
+    total_rows: Option<usize>,
+}
+impl PageSink {
+    async fn send(&self, event: CursorEvent) -> Result<(), AppError> {
+        self.events
+            .send(event)
+            .await
+            .map_err(|_| AppError::from("The cursor was closed"))
+    }
+}
+impl QuerySink for PageSink {
+    async fn columns(&mut self, columns: Vec<QueryColumn>) -> Result<(), AppError> {
+        let total_rows = self.total_rows.take();
+        self.send(CursorEvent::Columns {
+            columns: columns,
+            total_rows: total_rows,
+        })
+        .await
+    }
+    async fn row(&mut self, row: Vec<JsonValue>) -> Result<(), AppError> {
+        self.send(CursorEvent::Row(row)).await
+    }
+    async fn end_statement(&mut self, info: StatementInfo) -> Result<(), AppError> {
+        self.send(CursorEvent::End(info)).await
+    }
+    fn total_rows(&mut self, total_rows: usize) {
+        self.total_rows = Some(total_rows);
+    }
+}
+
+/**
+ * A query that is read one page at a time. The query keeps running in the background (and keeps
+ * its connection) until it is read to the end or the cursor is dropped.
+ */
+pub struct Cursor {
+    events: mpsc::Receiver<CursorEvent>,
+    task: JoinHandle<Result<(), AppError>>,
+    page_size: usize,
+    /// the statement being read, counted from 1
+    num_results: usize,
+    columns: Vec<QueryColumn>,
+    total_rows: Option<usize>,
+    /// whether the current statement has rows left
+    reading: bool,
+    /// rows of the current statement returned by previous pages
+    num_fetched: usize,
+    /// read ahead once the page is full, to know whether there is more
+    peeked: Option<CursorEvent>,
+    finished: bool,
+}
+impl Cursor {
+    pub fn open(
+        adapter: Connection,
+        query: String,
+        database: Option<String>,
+        query_id: Option<String>,
+        page_size: usize,
+    ) -> Cursor {
+        let (sender, events) = mpsc::channel(1);
+        let task = tokio::spawn(async move {
+            let mut sink = PageSink {
+                events: sender,
+                total_rows: None,
+            };
+            adapter
+                .query_stream(query, database, query_id, &mut sink)
+                .await
+        });
+        Cursor {
+            events: events,
+            task: task,
+            page_size: page_size.max(1),
+            num_results: 0,
+            columns: Vec::new(),
+            total_rows: None,
+            reading: false,
+            num_fetched: 0,
+            peeked: None,
+            finished: false,
+        }
+    }
+
+    /**
+     * Reads up to page_size rows, along with every statement that ends in between. The first
+     * result continues where the previous page stopped, with the same result_index. The last
+     * result is truncated as long as rows or statements are left.
+     */
+    pub async fn fetch_page(&mut self) -> Result<Vec<QueryResult>, AppError> {
+        let mut results: Vec<QueryResult> = Vec::new();
+        let mut start_time = SystemTime::now();
+        let mut rows: Vec<Vec<JsonValue>> = Vec::new();
+        let mut page_rows = 0;
+
+        loop {
+            let event = match self.next_event().await? {
+                Some(event) => event,
+                None => break,
+            };
+            match event {
+                CursorEvent::Row(_) | CursorEvent::Columns { .. }
+                    if page_rows == self.page_size =>
+                {
+                    self.peeked = Some(event);
+                    break;
+                }
+                CursorEvent::Columns {
+                    columns,
+                    total_rows,
+                } => {
+                    self.num_results += 1;
+                    self.columns = columns;
+                    self.total_rows = total_rows;
+                    self.num_fetched = 0;
+                    self.reading = true;
+                }
+                CursorEvent::Row(row) => {
+                    rows.push(row);
+                    page_rows += 1;
+                }
+                CursorEvent::End(info) => {
+                    let num_rows = self.num_fetched + rows.len();
+                    results.push(QueryResult {
+                        total_rows: Some(num_rows.to_string()),
+                        ..self
+                            .make_result(start_time, std::mem::take(&mut rows))
+                            .with_info(&info)
+                    });
+                    start_time = SystemTime::now();
+                    self.reading = false;
+                }
+            }
+        }
+
+        if self.reading {
+            // the page ended in the middle of a statement.
+            self.num_fetched += rows.len();
+            results.push(QueryResult {
+                truncated: true,
+                total_rows: self.total_rows.map(|total_rows| total_rows.to_string()),
+                ..self.make_result(start_time, rows)
+            });
+        } else if let (Some(_), Some(last)) = (&self.peeked, results.last_mut()) {
+            // more statements are left.
+            last.truncated = true;
+        }
+        Ok(results)
+    }
+
+    fn make_result(&self, start_time: SystemTime, rows: Vec<Vec<JsonValue>>) -> QueryResult {
+        QueryResult {
+            result_index: self.num_results.saturating_sub(1),
+            ..QueryResult::make(start_time, self.columns.clone(), rows)
+        }
+    }
+
+    async fn next_event(&mut self) -> Result<Option<CursorEvent>, AppError> {
+        if let Some(event) = self.peeked.take() {
+            return Ok(Some(event));
+        }
+        match self.events.recv().await {
+            Some(event) => Ok(Some(event)),
+            None => self.finish().await.map(|_| None),
+        }
+    }
+
+    /**
+     * Once the sink is dropped, the query is done. Returns the error it failed with, if any.
+     */
+    async fn finish(&mut self) -> Result<(), AppError> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        (&mut self.task).await.map_err(AppError::from)?
+    }
+}
+impl Drop for Cursor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/**
+ * A session's cursors by id, oldest first.
+ */
+type SessionCursors = VecDeque<(String, Arc<AsyncMutex<Cursor>>)>;
+
+/**
+ * The cursors of truncated results, by session id and cursor id.
+ */
+#[derive(Default)]
+pub struct Cursors {
+    sessions: Mutex<HashMap<String, SessionCursors>>,
+}
+impl Cursors {
+    pub fn insert(&self, session_id: &str, cursor: Cursor) -> Result<String, AppError> {
+        let cursor_id = crate::uuidv4!();
+        let mut sessions = self.sessions.lock().map_err(AppError::from)?;
+        let cursors = sessions.entry(session_id.to_string()).or_default();
+        if cursors.len() >= MAX_OPEN_CURSORS {
+            cursors.pop_front();
+        }
+        cursors.push_back((cursor_id.clone(), Arc::new(AsyncMutex::new(cursor))));
+        Ok(cursor_id)
+    }
+
+    pub fn get(
+        &self,
+        session_id: &str,
+        cursor_id: &str,
+    ) -> Result<Arc<AsyncMutex<Cursor>>, AppError> {
+        let sessions = self.sessions.lock().map_err(AppError::from)?;
+        sessions
+            .get(session_id)
+            .and_then(|cursors| cursors.iter().find(|(id, _)| id == cursor_id))
+            .map(|(_, cursor)| cursor.clone())
+            .ok_or(AppError::from(
+                "The cursor is closed. Run the query again to fetch more rows.",
+            ))
+    }
+
+    pub fn remove(&self, session_id: &str, cursor_id: &str) -> Result<bool, AppError> {
+        let mut sessions = self.sessions.lock().map_err(AppError::from)?;
+        let cursors = match sessions.get_mut(session_id) {
+            Some(cursors) => cursors,
+            None => return Ok(false),
+        };
+        let count = cursors.len();
+        cursors.retain(|(id, _)| id != cursor_id);
+        Ok(cursors.len() < count)
+    }
+
+    pub fn remove_session(&self, session_id: &str) -> Result<bool, AppError> {
+        let mut sessions = self.sessions.lock().map_err(AppError::from)?;
+        Ok(sessions.remove(session_id).is_some())
+    }
+}
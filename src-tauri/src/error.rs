@@ -0,0 +1,259 @@
+use serde::Serialize;
+use sqlx::postgres::{PgDatabaseError, PgErrorPosition};
+use sqlx::sqlite::SqliteError;
+
+/**
+ * What went wrong, so the frontend can tell e.g. a typo in the query from a lost connection
+ * without parsing the message.
+ */
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorKind {
+    /// the server (database or SSH) rejected the credentials
+    Auth,
+    /// authenticated, but not allowed to do that
+    Permission,
+    /// unreachable host, refused or dropped connection
+    Network,
+    Timeout,
+    /// an SSH host key that isn't trusted, or that changed
+    HostKey,
+    Syntax,
+    /// unknown database, table, column, function...
+    NotFound,
+    /// unique, foreign key, not null or check constraint violations
+    Constraint,
+    LockWait,
+    /// deadlocks and serialization failures, the transaction was rolled back
+    Deadlock,
+    /// any other error the database raised about the query
+    Query,
+    /// stopped through adapter_cancel, or dismissed by the user
+    Cancelled,
+    /// no connection is bound to the window
+    NotConnected,
+    /// a transaction command that doesn't fit the session, e.g. a commit without a transaction
+    Transaction,
+    /// the SSH tunnel the connection goes through failed
+    Tunnel,
+    /// any other SSH error
+    Ssh,
+    Other,
+}
+
+/**
+ * Serialized as { kind, error, code, sqlstate, position, retryable }, every field always present.
+ * code is the driver's own code (e.g. MySQL's 1064, or SQLite's extended result code), and
+ * position the 1-based character offset of the error in the query, if the driver points at one.
+ */
+#[derive(Serialize, Debug, Clone)]
+pub struct AppError {
+    pub kind: ErrorKind,
+    pub error: String,
+    pub code: Option<String>,
+    pub sqlstate: Option<String>,
+    pub position: Option<usize>,
+    /// whether running the same thing again may succeed, e.g. after a deadlock or a timeout
+    pub retryable: bool,
+}
+impl AppError {
+    pub fn new<E: std::fmt::Display>(kind: ErrorKind, err: E) -> AppError {
+        let error_string = format!("{}", err);
+        log::debug!("Error ({:?}): {}", kind, error_string);
+        AppError {
+            kind: kind,
+            error: error_string,
+            code: None,
+            sqlstate: None,
+            position: None,
+            retryable: matches!(
+                kind,
+                ErrorKind::Network | ErrorKind::Timeout | ErrorKind::LockWait | ErrorKind::Deadlock
+            ),
+        }
+    }
+
+    pub fn from<E: std::fmt::Display>(err: E) -> AppError {
+        AppError::new(ErrorKind::Other, err)
+    }
+
+    /**
+     * Returned by Adapter::query when the query was stopped through adapter_cancel
+     */
+    pub fn cancelled() -> AppError {
+        AppError::new(ErrorKind::Cancelled, "The query was cancelled")
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.kind == ErrorKind::Cancelled
+    }
+
+    pub fn mysql(err: mysql_async::Error) -> AppError {
+        match &err {
+            mysql_async::Error::Server(server) => AppError {
+                code: Some(server.code.to_string()),
+                sqlstate: Some(server.state.clone()),
+                ..AppError::new(mysql_kind(server.code, &server.state), &err)
+            },
+            mysql_async::Error::Io(mysql_async::IoError::Io(io)) => {
+                AppError::new(io_kind(io), &err)
+            }
+            mysql_async::Error::Io(_) => AppError::new(ErrorKind::Network, &err),
+            _ => AppError::new(ErrorKind::Other, &err),
+        }
+    }
+
+    /**
+     * Covers both postgres and sqlite, which report their codes differently: postgres' code is
+     * the SQLSTATE, sqlite's is its extended result code.
+     */
+    pub fn sqlx(err: sqlx::Error) -> AppError {
+        match &err {
+            sqlx::Error::Database(db) => {
+                let code = db.code().map(|code| code.to_string());
+                if let Some(pg) = db.try_downcast_ref::<PgDatabaseError>() {
+                    AppError {
+                        code: code.clone(),
+                        sqlstate: code,
+                        position: match pg.position() {
+                            Some(PgErrorPosition::Original(position)) => Some(position),
+                            _ => None,
+                        },
+                        ..AppError::new(sqlstate_kind(pg.code()), &err)
+                    }
+                } else if db.try_downcast_ref::<SqliteError>().is_some() {
+                    let kind = sqlite_kind(code.as_deref().unwrap_or_default(), db.message());
+                    AppError {
+                        code: code,
+                        ..AppError::new(kind, &err)
+                    }
+                } else {
+                    AppError {
+                        code: code,
+                        ..AppError::new(ErrorKind::Query, &err)
+                    }
+                }
+            }
+            sqlx::Error::Io(io) => AppError::new(io_kind(io), &err),
+            sqlx::Error::Tls(_) | sqlx::Error::Protocol(_) => {
+                AppError::new(ErrorKind::Network, &err)
+            }
+            sqlx::Error::PoolTimedOut => AppError::new(ErrorKind::Timeout, &err),
+            sqlx::Error::PoolClosed => AppError::new(ErrorKind::NotConnected, &err),
+            _ => AppError::new(ErrorKind::Other, &err),
+        }
+    }
+
+    /**
+     * The SSH module reports through anyhow. Whatever russh (or the module itself) raised is
+     * still in there, along with any context added on the way, e.g. the hop that failed.
+     */
+    pub fn ssh(err: anyhow::Error) -> AppError {
+        let message = err.to_string();
+        if let Some(app_error) = err.downcast_ref::<AppError>() {
+            return AppError {
+                error: message,
+                ..app_error.clone()
+            };
+        }
+        let kind = if let Some(russh_error) = err.downcast_ref::<russh::Error>() {
+            match russh_error {
+                russh::Error::IO(io) => io_kind(io),
+                russh::Error::ConnectionTimeout
+                | russh::Error::KeepaliveTimeout
+                | russh::Error::InactivityTimeout => ErrorKind::Timeout,
+                russh::Error::Disconnect | russh::Error::HUP => ErrorKind::Network,
+                russh::Error::ChannelOpenFailure(_) => ErrorKind::Network,
+                russh::Error::NotAuthenticated => ErrorKind::Auth,
+                russh::Error::UnknownKey | russh::Error::WrongServerSig => ErrorKind::HostKey,
+                _ => ErrorKind::Ssh,
+            }
+        } else if err.downcast_ref::<russh_keys::Error>().is_some() {
+            // the key couldn't be loaded (e.g. a wrong passphrase), or the agent refused it.
+            ErrorKind::Auth
+        } else if let Some(io) = err.downcast_ref::<std::io::Error>() {
+            io_kind(io)
+        } else {
+            ErrorKind::Ssh
+        };
+        AppError::new(kind, message)
+    }
+}
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+impl std::error::Error for AppError {}
+
+fn io_kind(err: &std::io::Error) -> ErrorKind {
+    match err.kind() {
+        std::io::ErrorKind::TimedOut => ErrorKind::Timeout,
+        std::io::ErrorKind::PermissionDenied => ErrorKind::Permission,
+        std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+        _ => ErrorKind::Network,
+    }
+}
+
+/**
+ * Most errors are told apart by their SQLSTATE class (the first two characters), postgres being
+ * the most precise about them.
+ */
+fn sqlstate_kind(sqlstate: &str) -> ErrorKind {
+    match sqlstate {
+        "42601" => ErrorKind::Syntax,
+        "42501" => ErrorKind::Permission,
+        "42P01" | "42703" | "42704" | "42883" | "3D000" | "3F000" | "42S02" | "42S22" => {
+            ErrorKind::NotFound
+        }
+        "55P03" => ErrorKind::LockWait,
+        "57014" => ErrorKind::Cancelled,
+        "57P01" | "57P02" | "57P03" => ErrorKind::Network,
+        _ => match sqlstate.get(..2).unwrap_or_default() {
+            "08" => ErrorKind::Network,
+            "23" => ErrorKind::Constraint,
+            "28" => ErrorKind::Auth,
+            "40" => ErrorKind::Deadlock,
+            _ => ErrorKind::Query,
+        },
+    }
+}
+
+/**
+ * MySQL lumps most errors into SQLSTATE 42000 or HY000, so its own code says more.
+ */
+fn mysql_kind(code: u16, sqlstate: &str) -> ErrorKind {
+    match code {
+        1045 | 1698 => ErrorKind::Auth,
+        1044 | 1142 | 1143 | 1227 => ErrorKind::Permission,
+        1064 | 1149 => ErrorKind::Syntax,
+        1049 | 1051 | 1054 | 1146 | 1305 => ErrorKind::NotFound,
+        1048 | 1062 | 1216 | 1217 | 1451 | 1452 | 3819 => ErrorKind::Constraint,
+        1205 => ErrorKind::LockWait,
+        1213 => ErrorKind::Deadlock,
+        1317 => ErrorKind::Cancelled,
+        // max_execution_time was exceeded
+        3024 => ErrorKind::Timeout,
+        1040 | 1053 => ErrorKind::Network,
+        _ => sqlstate_kind(sqlstate),
+    }
+}
+
+/**
+ * SQLite's primary result code is the low byte of the extended one. Syntax errors and missing
+ * tables share the generic SQLITE_ERROR, only the message tells them apart.
+ */
+fn sqlite_kind(code: &str, message: &str) -> ErrorKind {
+    match code.parse::<i32>().map(|code| code & 0xff) {
+        Ok(5) | Ok(6) => ErrorKind::LockWait,
+        Ok(19) => ErrorKind::Constraint,
+        Ok(3) | Ok(23) => ErrorKind::Permission,
+        Ok(9) => ErrorKind::Cancelled,
+        Ok(14) => ErrorKind::NotFound,
+        _ if message.contains("syntax error") || message.contains("incomplete input") => {
+            ErrorKind::Syntax
+        }
+        _ if message.starts_with("no such") => ErrorKind::NotFound,
+        _ => ErrorKind::Query,
+    }
+}
@@ -1,18 +1,44 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 #![allow(async_fn_in_trait)]
+// fields are spelled out in struct literals, e.g. `rows: rows`
+#![allow(clippy::redundant_field_names)]
 
-use adapters::{connect_adapter, Adapter, AdapterEnum, AdapterOpts, QueryResult};
+use adapters::{
+    connect_adapter, Adapter, AdapterOpts, AutoRollbackListener, Connection, JsonValue,
+    QueryResult, TransactionInfo,
+};
+use cursor::{Cursor, Cursors, DEFAULT_PAGE_SIZE};
+pub use error::AppError;
+use error::ErrorKind;
+use futures::FutureExt;
+use prompt::Prompts;
 use rand::{thread_rng, Rng};
-use serde::Serialize;
-use std::{collections::HashMap, sync::Mutex};
+use session::{SessionInfo, Sessions};
+use ssh::{
+    HostKeyInfo, HostKeyPrompt, HostKeyVerifier, KeyboardInteractiveHandler,
+    KeyboardInteractiveRequest, SshContext, TunnelStatus, TunnelStatusListener,
+};
+use ssh_config::SshConfig;
+use std::sync::Arc;
+use std::time::Duration;
 use users::get_current_username;
 // use tauri::{menu::{Menu, MenuItem, PredefinedMenuItem, Submenu}, Runtime, State, Window};
-use tauri::{State, Window};
+use stream::{ChannelSink, QueryStreamEvent, QueryStreamSummary};
+use tauri::ipc::Channel;
+use tauri::{AppHandle, Emitter, Manager, State, Window, WindowEvent};
+use tauri_plugin_shell::ShellExt;
 
 pub mod adapters;
+pub mod cursor;
+pub mod error;
+pub mod logger;
 pub mod menu;
+pub mod prompt;
+pub mod session;
 pub mod ssh;
+pub mod ssh_config;
+pub mod stream;
 pub mod tests;
 
 #[macro_export]
@@ -22,108 +48,599 @@ macro_rules! uuidv4 {
     };
 }
 
-#[derive(Serialize, Debug)]
-pub struct AppError {
-    pub error: String,
+#[derive(Default)]
+pub struct AppState {
+    sessions: Sessions,
+    cursors: Cursors,
+    prompts: Prompts,
 }
-impl AppError {
-    pub fn from<E: std::fmt::Display>(err: E) -> AppError {
-        let error_string = format!("{}", err);
-        println!("Error: {}", error_string); // debug!
-        AppError {
-            error: error_string,
+impl AppState {
+    /**
+     * Returns a clone of the session's connection, which shares the pool and tunnel. Sessions of
+     * other windows can't be reached.
+     */
+    pub fn get_adapter(
+        &self,
+        window_label: &str,
+        session_id: &str,
+    ) -> Result<Connection, AppError> {
+        self.sessions.get(window_label, session_id)
+    }
+
+    /**
+     * The connection to run a query (or a transaction statement) on. Background queries (e.g.
+     * listing the tables) run on the pool. Other queries of a pinned session run one at a time on
+     * its own connection, so the session's open cursors, which hold on to it, are closed first.
+     */
+    pub fn get_query_adapter(
+        &self,
+        window_label: &str,
+        session_id: &str,
+        background: bool,
+    ) -> Result<Connection, AppError> {
+        let adapter = self.sessions.get(window_label, session_id)?;
+        if background {
+            return Ok(adapter.pooled());
+        }
+        if adapter.is_pinned() {
+            self.cursors.remove_session(session_id)?;
         }
+        Ok(adapter)
     }
-}
-impl std::fmt::Display for AppError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.error)
+
+    /**
+     * Binds the connection to the window, returning the id of the new session.
+     */
+    pub fn add_adapter(&self, window_label: &str, title: &str, adapter: Connection) -> String {
+        self.sessions.insert(window_label, title, adapter)
+    }
+
+    pub fn remove_adapter(
+        &self,
+        window_label: &str,
+        session_id: &str,
+    ) -> Result<Connection, AppError> {
+        let adapter = self.sessions.remove(window_label, session_id)?;
+        self.cursors.remove_session(session_id)?;
+        Ok(adapter)
     }
-}
-impl std::error::Error for AppError {}
 
-#[derive(Default)]
-pub struct AppState {
-    adapters: Mutex<HashMap<String, AdapterEnum>>,
-}
-impl AppState {
     /**
-     * Returns a cloned version of the value in the map. If issues occur, try implementing a Deref.
-     * @see https://doc.rust-lang.org/book/ch04-02-references-and-borrowing.html
-     * @see https://doc.rust-lang.org/book/ch15-02-deref.html
+     * Unbinds every session of the window, returning their connections to disconnect.
      */
-    pub fn get_adapter(&self, window: &Window) -> Result<AdapterEnum, AppError> {
-        let uuid: String = window.label().into();
-        let map_mutex = self.adapters.try_lock().map_err(AppError::from)?;
-        match map_mutex.get(&uuid) {
-            Some(adapter) => Ok(adapter.clone()),
-            None => Err(AppError::from(
-                "Unable to get_adapter. No connection bound to the window!",
-            )),
+    pub fn remove_window(&self, window_label: &str) -> Result<Vec<Connection>, AppError> {
+        let mut connections: Vec<Connection> = Vec::new();
+        for (session_id, connection) in self.sessions.remove_window(window_label) {
+            self.cursors.remove_session(&session_id)?;
+            connections.push(connection);
         }
+        Ok(connections)
+    }
+
+    /**
+     * Every live session, of every window, oldest first.
+     */
+    pub fn list_sessions(&self) -> Vec<SessionInfo> {
+        self.sessions.list()
+    }
+
+    pub fn window_session_count(&self, window_label: &str) -> usize {
+        self.sessions.count(window_label)
+    }
+
+    /**
+     * The sessions of the window holding a transaction open.
+     */
+    pub fn uncommitted_sessions(&self, window_label: &str) -> Vec<SessionInfo> {
+        self.sessions
+            .list()
+            .into_iter()
+            .filter(|session| session.window_label == window_label)
+            .filter(|session| {
+                session
+                    .transaction
+                    .as_ref()
+                    .is_some_and(|transaction| transaction.open)
+            })
+            .collect()
     }
-    pub fn set_adapter(&self, window: &Window, adapter: AdapterEnum) -> Result<bool, AppError> {
-        self.adapters
-            .try_lock()
-            .map_err(AppError::from)?
-            .insert(window.label().to_string(), adapter.clone());
-        Ok(true)
+}
+
+/**
+ * Sent along with the "uncommitted-transaction" prompt.
+ */
+#[derive(serde::Serialize, Clone)]
+struct UncommittedTransactions {
+    sessions: Vec<SessionInfo>,
+}
+
+/**
+ * Asks the user whether to roll back the transactions of the sessions about to be disconnected.
+ * A dismissed (or timed out) prompt is a no.
+ */
+async fn confirm_rollback(window: &Window, prompts: &Prompts, sessions: Vec<SessionInfo>) -> bool {
+    match prompts
+        .ask(
+            window,
+            "uncommitted-transaction",
+            UncommittedTransactions { sessions: sessions },
+        )
+        .await
+    {
+        Ok(answer) => answer.as_bool().unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/**
+ * Host keys are checked against ~/.ssh/known_hosts and the app's own trust store. Unknown keys
+ * are confirmed by the user through the "ssh-host-key" prompt, and keyboard-interactive
+ * authentication goes through the "ssh-keyboard-interactive" prompt. Tunnels report losing and
+ * regaining their session through the "tunnel-status" event.
+ */
+fn ssh_context(window: &Window, state: &AppState) -> SshContext {
+    let prompts = state.prompts.clone();
+    let prompt_window = window.clone();
+    let prompt: HostKeyPrompt = Arc::new(move |host_key: HostKeyInfo| {
+        let prompts = prompts.clone();
+        let window = prompt_window.clone();
+        async move {
+            match prompts.ask(&window, "ssh-host-key", host_key).await {
+                Ok(answer) => answer.as_bool().unwrap_or(false),
+                Err(_) => false,
+            }
+        }
+        .boxed()
+    });
+    let prompts = state.prompts.clone();
+    let prompt_window = window.clone();
+    let keyboard_interactive: KeyboardInteractiveHandler =
+        Arc::new(move |request: KeyboardInteractiveRequest| {
+            let prompts = prompts.clone();
+            let window = prompt_window.clone();
+            async move {
+                let answer = prompts
+                    .ask(&window, "ssh-keyboard-interactive", request)
+                    .await
+                    .ok()?;
+                serde_json::from_value::<Vec<String>>(answer).ok()
+            }
+            .boxed()
+        });
+    let status_window = window.clone();
+    let on_status: TunnelStatusListener = Arc::new(move |status: TunnelStatus| {
+        status_window
+            .emit_to(status_window.label(), "tunnel-status", status)
+            .unwrap_or_default();
+    });
+
+    SshContext {
+        host_keys: HostKeyVerifier {
+            known_hosts: window
+                .path()
+                .home_dir()
+                .map(|home| home.join(".ssh").join("known_hosts"))
+                .into_iter()
+                .collect(),
+            trust_store: window
+                .path()
+                .app_local_data_dir()
+                .map(|dir| dir.join("known_hosts"))
+                .ok(),
+            prompt: Some(prompt),
+        },
+        agent_socket: None,
+        on_status: Some(on_status),
+        keyboard_interactive: Some(keyboard_interactive),
     }
-    pub fn remove_adapter(&self, window: &Window) -> Result<AdapterEnum, AppError> {
-        let uuid: String = window.label().into();
-        self.adapters
-            .try_lock()
-            .map_err(AppError::from)?
-            .remove(&uuid)
-            .ok_or(AppError::from(
-                "Unable to remove_adapter. No connection bound to the window!",
-            ))
+}
+
+/**
+ * ~/.ssh/config is read on every call, so edits show up without a restart.
+ */
+fn ssh_config(window: &Window) -> Result<SshConfig, AppError> {
+    let home_dir = window.path().home_dir().ok();
+    match &home_dir {
+        Some(home) => SshConfig::load(&home.join(".ssh").join("config"), home_dir.clone())
+            .map_err(AppError::from),
+        None => Ok(SshConfig::default()),
     }
 }
 
+/**
+ * The host aliases of ~/.ssh/config.
+ */
+#[tauri::command]
+async fn ssh_config_hosts(window: Window) -> Result<Vec<String>, AppError> {
+    Ok(ssh_config(&window)?.aliases())
+}
+
+/**
+ * The hops to reach a host alias of ~/.ssh/config, its ProxyJump hosts included.
+ */
+#[tauri::command]
+async fn ssh_config_resolve(window: Window, alias: String) -> Result<ssh::SshOpts, AppError> {
+    ssh_config(&window)?
+        .resolve(alias.as_str())
+        .map_err(AppError::from)
+}
+
+/**
+ * Opens a session in the window, resolving with its id. Every other adapter_* command addresses
+ * a session by that id, until adapter_disconnect.
+ * A pinned session keeps one connection to itself, so session variables, temporary tables and
+ * the like carry over from one query to the next.
+ */
 #[tauri::command]
 async fn adapter_connect(
     window: Window,
     title: String,
     driver_opts: AdapterOpts,
     ssh_opts: Option<ssh::SshOpts>,
+    pinned: Option<bool>,
     state: State<'_, AppState>,
-) -> Result<bool, AppError> {
-    let adapter = connect_adapter(driver_opts, ssh_opts).await?;
+) -> Result<String, AppError> {
+    let ssh_context = ssh_context(&window, &state);
+    let mut adapter = connect_adapter(driver_opts, ssh_opts, ssh_context).await?;
+    if pinned.unwrap_or(false) {
+        adapter = match adapter.pin().await {
+            Ok(pinned) => pinned,
+            Err(why) => {
+                adapter.disconnect().await.unwrap_or_default();
+                return Err(why);
+            }
+        };
+    }
     window.set_title(title.as_str()).unwrap_or_default();
-    state.set_adapter(&window, adapter)
+    Ok(state.add_adapter(window.label(), title.as_str(), adapter))
 }
 
+/**
+ * Runs a query or a script, resolving with one QueryResult per statement.
+ * Reads at most page_size rows (DEFAULT_PAGE_SIZE if omitted, 0 for no limit). If rows or
+ * statements are left, the last result is truncated and carries a cursor_id to pass to
+ * adapter_fetch_page.
+ * Background queries run on the pool, even for a pinned session.
+ */
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn adapter_query(
     window: Window,
+    session_id: String,
+    query: String,
+    database: Option<String>,
+    query_id: Option<String>,
+    page_size: Option<usize>,
+    background: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<Vec<QueryResult>, AppError> {
+    let adapter =
+        state.get_query_adapter(window.label(), &session_id, background.unwrap_or(false))?;
+    let page_size = page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+    if page_size == 0 {
+        return adapter.query(query, database, query_id).await;
+    }
+
+    let mut cursor = Cursor::open(adapter, query, database, query_id, page_size);
+    let mut results = cursor.fetch_page().await?;
+    if let Some(last) = results.last_mut().filter(|last| last.truncated) {
+        last.cursor_id = Some(state.cursors.insert(&session_id, cursor)?);
+    }
+    Ok(results)
+}
+
+/**
+ * Reads the next page of a truncated result. The first result continues the statement the
+ * previous page stopped in. The cursor is closed once the last page was read.
+ */
+#[tauri::command]
+async fn adapter_fetch_page(
+    window: Window,
+    session_id: String,
+    cursor_id: String,
+    state: State<'_, AppState>,
+) -> Result<Vec<QueryResult>, AppError> {
+    state.get_adapter(window.label(), &session_id)?;
+    let cursor = state.cursors.get(&session_id, &cursor_id)?;
+    let mut results = cursor.lock().await.fetch_page().await;
+    match results.as_mut().map(|results| results.last_mut()) {
+        Ok(Some(last)) if last.truncated => last.cursor_id = Some(cursor_id),
+        _ => {
+            state.cursors.remove(&session_id, &cursor_id)?;
+        }
+    };
+    results
+}
+
+/**
+ * Stops reading a truncated result, releasing its connection.
+ */
+#[tauri::command]
+async fn adapter_close_cursor(
+    window: Window,
+    session_id: String,
+    cursor_id: String,
+    state: State<'_, AppState>,
+) -> Result<bool, AppError> {
+    state.get_adapter(window.label(), &session_id)?;
+    state.cursors.remove(&session_id, &cursor_id)
+}
+
+/**
+ * Same as adapter_query, but the rows are sent over on_event in batches of batch_size as they are
+ * read, instead of all at once. Resolves with the summary once the last batch was sent.
+ */
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn adapter_query_stream(
+    window: Window,
+    session_id: String,
     query: String,
     database: Option<String>,
+    query_id: Option<String>,
+    batch_size: Option<usize>,
+    background: Option<bool>,
+    on_event: Channel<QueryStreamEvent>,
+    state: State<'_, AppState>,
+) -> Result<QueryStreamSummary, AppError> {
+    let adapter =
+        state.get_query_adapter(window.label(), &session_id, background.unwrap_or(false))?;
+    let mut sink = ChannelSink::new(on_event, batch_size);
+    adapter
+        .query_stream(query, database, query_id, &mut sink)
+        .await?;
+    sink.finish()
+}
+
+/**
+ * Stops a query started by adapter_query with the same query_id. Resolves to false if the query
+ * is no longer running.
+ */
+#[tauri::command]
+async fn adapter_cancel(
+    window: Window,
+    session_id: String,
+    query_id: String,
+    state: State<'_, AppState>,
+) -> Result<bool, AppError> {
+    let adapter = state.get_adapter(window.label(), &session_id)?;
+    adapter.cancel(query_id).await
+}
+
+/**
+ * Starts a transaction in a pinned session, spanning the queries to come until adapter_commit or
+ * adapter_rollback. With auto_rollback_secs, the transaction is rolled back once no query ran in
+ * it for that long, and the window gets a "transaction-rolled-back" event.
+ */
+#[tauri::command]
+async fn adapter_begin(
+    window: Window,
+    session_id: String,
+    auto_rollback_secs: Option<u64>,
+    state: State<'_, AppState>,
+) -> Result<TransactionInfo, AppError> {
+    let adapter = state.get_query_adapter(window.label(), &session_id, false)?;
+    let rollback_window = window.clone();
+    let on_auto_rollback: AutoRollbackListener = Arc::new(move |info: TransactionInfo| {
+        rollback_window
+            .emit_to(
+                rollback_window.label(),
+                "transaction-rolled-back",
+                serde_json::json!({ "session_id": session_id, "transaction": info }),
+            )
+            .unwrap_or_default();
+    });
+    adapter
+        .begin(
+            auto_rollback_secs
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            Some(on_auto_rollback),
+        )
+        .await
+}
+
+#[tauri::command]
+async fn adapter_commit(
+    window: Window,
+    session_id: String,
     state: State<'_, AppState>,
-) -> Result<QueryResult, AppError> {
-    let adapter = state.get_adapter(&window)?;
-    adapter.query(query, database).await
+) -> Result<TransactionInfo, AppError> {
+    let adapter = state.get_query_adapter(window.label(), &session_id, false)?;
+    adapter.commit().await
 }
 
+/**
+ * Rolls back the whole transaction, or only what was done since the savepoint.
+ */
 #[tauri::command]
-async fn adapter_disconnect(window: Window, state: State<'_, AppState>) -> Result<bool, AppError> {
-    let mut adapter: AdapterEnum = state.remove_adapter(&window)?;
+async fn adapter_rollback(
+    window: Window,
+    session_id: String,
+    savepoint: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<TransactionInfo, AppError> {
+    let adapter = state.get_query_adapter(window.label(), &session_id, false)?;
+    adapter.rollback(savepoint).await
+}
+
+#[tauri::command]
+async fn adapter_savepoint(
+    window: Window,
+    session_id: String,
+    name: String,
+    state: State<'_, AppState>,
+) -> Result<TransactionInfo, AppError> {
+    let adapter = state.get_query_adapter(window.label(), &session_id, false)?;
+    adapter.savepoint(name).await
+}
+
+/**
+ * The state of the session's transaction. Only pinned sessions have one.
+ */
+#[tauri::command]
+fn adapter_transaction(
+    window: Window,
+    session_id: String,
+    state: State<'_, AppState>,
+) -> Result<Option<TransactionInfo>, AppError> {
+    Ok(state
+        .get_adapter(window.label(), &session_id)?
+        .transaction_info())
+}
+
+/**
+ * If the session holds a transaction open, the user is asked to confirm it is rolled back first.
+ * The disconnect is cancelled otherwise.
+ */
+#[tauri::command]
+async fn adapter_disconnect(
+    window: Window,
+    session_id: String,
+    state: State<'_, AppState>,
+) -> Result<bool, AppError> {
+    let uncommitted: Vec<SessionInfo> = state
+        .uncommitted_sessions(window.label())
+        .into_iter()
+        .filter(|session| session.session_id == session_id)
+        .collect();
+    if !uncommitted.is_empty() && !confirm_rollback(&window, &state.prompts, uncommitted).await {
+        return Err(AppError::new(
+            ErrorKind::Cancelled,
+            "The disconnect was cancelled, the transaction is still open",
+        ));
+    }
+    let mut adapter: Connection = state.remove_adapter(window.label(), &session_id)?;
     adapter.disconnect().await?;
-    window.set_title("New Connection").unwrap_or_default();
+    if state.window_session_count(window.label()) == 0 {
+        window.set_title("New Connection").unwrap_or_default();
+    }
     Ok(true)
 }
 
+/**
+ * Lists the live sessions of every window, with their age and tunnel status, for debugging.
+ */
+#[tauri::command]
+fn adapter_sessions(state: State<'_, AppState>) -> Vec<SessionInfo> {
+    state.list_sessions()
+}
+
+/**
+ * Keeps a window with uncommitted transactions open until the user agrees to roll them back.
+ * Returns whether the close was held off.
+ */
+fn hold_close(window: &Window) -> bool {
+    let state = window.state::<AppState>();
+    let uncommitted = state.uncommitted_sessions(window.label());
+    if uncommitted.is_empty() {
+        return false;
+    }
+    let prompts = state.prompts.clone();
+    let window = window.clone();
+    tauri::async_runtime::spawn(async move {
+        if confirm_rollback(&window, &prompts, uncommitted).await {
+            // destroying skips CloseRequested, the sessions are then disconnected as usual.
+            if let Err(why) = window.destroy() {
+                log::error!("Unable to close window {}! {:?}", window.label(), why);
+            }
+        }
+    });
+    true
+}
+
+/**
+ * Disconnects every session of a window once it is gone, so their pools and tunnels don't
+ * outlive it.
+ */
+fn disconnect_window(window: &Window) {
+    let state = window.state::<AppState>();
+    let connections = match state.remove_window(window.label()) {
+        Ok(connections) => connections,
+        Err(why) => {
+            log::error!(
+                "Unable to disconnect the window {}: {}",
+                window.label(),
+                why
+            );
+            return;
+        }
+    };
+    if !connections.is_empty() {
+        log::info!(
+            "Disconnecting {} session(s) of the closed window {}",
+            connections.len(),
+            window.label()
+        );
+    }
+    for mut connection in connections {
+        tauri::async_runtime::spawn(async move {
+            if let Err(why) = connection.disconnect().await {
+                log::warn!("Failed to disconnect a closed window's session: {}", why);
+            }
+        });
+    }
+}
+
 #[tauri::command]
 async fn adapter_test(
+    window: Window,
     driver_opts: AdapterOpts,
     ssh_opts: Option<ssh::SshOpts>,
+    state: State<'_, AppState>,
 ) -> Result<String, AppError> {
-    let mut adapter = connect_adapter(driver_opts, ssh_opts).await?;
+    let ssh_context = ssh_context(&window, &state);
+    let mut adapter = connect_adapter(driver_opts, ssh_opts, ssh_context).await?;
     adapter.disconnect().await?;
 
     Ok("The connection test was a success!".to_string())
 }
 
+/**
+ * Answers a prompt the backend sent to the window, e.g. true to trust an unknown host key.
+ */
+#[tauri::command]
+fn prompt_answer(
+    window: Window,
+    prompt_id: String,
+    answer: JsonValue,
+    state: State<'_, AppState>,
+) -> Result<bool, AppError> {
+    state.prompts.answer(window.label(), &prompt_id, answer)
+}
+
+/**
+ * The last lines of the log, 200 by default.
+ */
+#[tauri::command]
+fn log_tail(lines: Option<usize>) -> Result<Vec<String>, AppError> {
+    logger::tail(lines.unwrap_or(200))
+}
+
+/**
+ * Opens the log file with the default app for it.
+ */
+#[tauri::command]
+fn log_open(app: AppHandle) -> Result<bool, AppError> {
+    let path = logger::log_path()?;
+    #[allow(deprecated)]
+    app.shell()
+        .open(path.to_string_lossy(), None)
+        .map_err(AppError::from)?;
+    Ok(true)
+}
+
+/**
+ * Sets the literal patterns (e.g. internal host names) to redact from the log, on top of the
+ * passwords and keys that always are.
+ */
+#[tauri::command]
+fn log_set_redactions(app: AppHandle, patterns: Vec<String>) -> Result<bool, AppError> {
+    let config_dir = app.path().app_config_dir().map_err(AppError::from)?;
+    logger::save_patterns(&config_dir, patterns)?;
+    Ok(true)
+}
+
 #[tauri::command]
 fn fetch_key() -> Result<String, String> {
     let service = "torqle";
@@ -167,14 +684,39 @@ fn main() {
         .plugin(tauri_plugin_global_shortcut::Builder::new().build())
         .plugin(tauri_plugin_fs::init())
         .manage(AppState::default())
+        .on_window_event(|window, event| match event {
+            WindowEvent::CloseRequested { api, .. } if hold_close(window) => api.prevent_close(),
+            WindowEvent::Destroyed => disconnect_window(window),
+            _ => (),
+        })
         .invoke_handler(tauri::generate_handler![
             adapter_connect,
             adapter_disconnect,
             adapter_test,
             adapter_query,
+            adapter_query_stream,
+            adapter_fetch_page,
+            adapter_close_cursor,
+            prompt_answer,
+            ssh_config_hosts,
+            ssh_config_resolve,
+            adapter_cancel,
+            adapter_sessions,
+            adapter_begin,
+            adapter_commit,
+            adapter_rollback,
+            adapter_savepoint,
+            adapter_transaction,
+            log_tail,
+            log_open,
+            log_set_redactions,
             fetch_key
         ])
         .setup(|app_handle| -> Result<(), Box<dyn std::error::Error>> {
+            logger::init(
+                app_handle.path().app_log_dir().ok(),
+                app_handle.path().app_config_dir().ok(),
+            );
             menu::customize(app_handle)?;
             // other app setup code can go here...
             Ok(())
@@ -13,10 +13,7 @@ pub fn customize(app_handle: &mut App) -> Result<bool, tauri::Error> {
 
     let menu_items = menu.items()?.clone();
 
-    let submenus = menu_items
-        .iter()
-        .filter(|item| item.as_submenu().is_some())
-        .map(|item| item.as_submenu().unwrap());
+    let submenus = menu_items.iter().filter_map(|item| item.as_submenu());
 
     let file_menu = match submenus
         .clone()
@@ -66,7 +63,7 @@ pub fn customize(app_handle: &mut App) -> Result<bool, tauri::Error> {
     app_handle.set_menu(menu)?;
 
     app_handle.on_menu_event(move |app, event| {
-        println!("menu event: {:?}", event);
+        log::debug!("menu event: {:?}", event);
         match event.id().0.as_str() {
             "new_window" => {
                 let url = WebviewUrl::App("/".into());
@@ -76,14 +73,17 @@ pub fn customize(app_handle: &mut App) -> Result<bool, tauri::Error> {
                     WebviewWindowBuilder::new(app, id, url).inner_size(1280.0, 800.0);
 
                 match window_builder.build() {
-                    Ok(_) => println!("Opened window {}", id_clone),
-                    Err(why) => println!("Unable to open window {}! {:?}", id_clone, why),
+                    Ok(_) => log::debug!("Opened window {}", id_clone),
+                    Err(why) => log::error!("Unable to open window {}! {:?}", id_clone, why),
                 };
             }
+            // the window's sessions are disconnected once it is destroyed, see disconnect_window.
             "close_window" => {
                 app.webview_windows().iter_mut().for_each(|window| {
                     if window.1.is_focused().unwrap_or(false) {
-                        window.1.close().unwrap();
+                        if let Err(why) = window.1.close() {
+                            log::error!("Unable to close window {}! {:?}", window.0, why);
+                        }
                     }
                 });
             }
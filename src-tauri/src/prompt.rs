@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use tauri::{Emitter, Window};
+use tokio::sync::oneshot;
+
+use crate::adapters::JsonValue;
+use crate::error::ErrorKind;
+use crate::AppError;
+
+/// unanswered prompts are given up on after this long, failing whatever was waiting on them
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/**
+ * Sent to the window along with the prompt id to pass to prompt_answer.
+ */
+#[derive(Serialize, Clone)]
+struct PromptEvent<T: Serialize + Clone> {
+    prompt_id: String,
+    #[serde(flatten)]
+    payload: T,
+}
+
+/**
+ * A prompt waiting on its answer, which only the window it was asked of can give.
+ */
+struct PendingPrompt {
+    window_label: String,
+    sender: oneshot::Sender<JsonValue>,
+}
+
+/**
+ * Questions the backend is waiting on the user for, e.g. whether to trust an unknown host key.
+ * A prompt is sent to the window as an event and answered through the prompt_answer command.
+ */
+#[derive(Clone, Default)]
+pub struct Prompts {
+    pending: Arc<Mutex<HashMap<String, PendingPrompt>>>,
+}
+impl Prompts {
+    pub async fn ask<T: Serialize + Clone>(
+        &self,
+        window: &Window,
+        event: &str,
+        payload: T,
+    ) -> Result<JsonValue, AppError> {
+        let prompt_id = crate::uuidv4!();
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().map_err(AppError::from)?.insert(
+            prompt_id.clone(),
+            PendingPrompt {
+                window_label: window.label().to_string(),
+                sender: sender,
+            },
+        );
+
+        let prompt = PromptEvent {
+            prompt_id: prompt_id.clone(),
+            payload: payload,
+        };
+        let answer = match window.emit_to(window.label(), event, prompt) {
+            Ok(_) => tokio::time::timeout(PROMPT_TIMEOUT, receiver).await,
+            Err(why) => {
+                self.forget(&prompt_id);
+                return Err(AppError::from(why));
+            }
+        };
+        match answer {
+            Ok(Ok(answer)) => Ok(answer),
+            Ok(Err(_)) => Err(AppError::new(
+                ErrorKind::Cancelled,
+                "The prompt was dismissed",
+            )),
+            Err(_) => {
+                self.forget(&prompt_id);
+                Err(AppError::new(ErrorKind::Timeout, "The prompt timed out"))
+            }
+        }
+    }
+
+    /**
+     * Resolves to false if nothing is waiting on the prompt (anymore). A prompt asked of another
+     * window is left waiting, as if it didn't exist.
+     */
+    pub fn answer(
+        &self,
+        window_label: &str,
+        prompt_id: &str,
+        answer: JsonValue,
+    ) -> Result<bool, AppError> {
+        let mut pending = self.pending.lock().map_err(AppError::from)?;
+        match pending.get(prompt_id) {
+            Some(prompt) if prompt.window_label == window_label => (),
+            _ => return Ok(false),
+        }
+        match pending.remove(prompt_id) {
+            Some(prompt) => Ok(prompt.sender.send(answer).is_ok()),
+            None => Ok(false),
+        }
+    }
+
+    fn forget(&self, prompt_id: &str) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.remove(prompt_id);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::adapters::{Connection, TransactionInfo};
+use crate::error::ErrorKind;
+use crate::ssh::TunnelStatus;
+use crate::AppError;
+
+/**
+ * A connection opened by adapter_connect. A window may hold several, e.g. one per tab.
+ */
+struct Session {
+    window_label: String,
+    /// the title the window was given on connect
+    title: String,
+    connection: Connection,
+    connected_at: DateTime<Local>,
+}
+
+/**
+ * What adapter_sessions reports about a session.
+ */
+#[derive(Serialize, Debug, Clone)]
+pub struct SessionInfo {
+    pub session_id: String,
+    pub window_label: String,
+    pub title: String,
+    pub connected_at: DateTime<Local>,
+    pub age_secs: i64,
+    /// None when the session doesn't go through a tunnel
+    pub tunnel: Option<TunnelStatus>,
+    /// whether the session keeps a connection to itself
+    pub pinned: bool,
+    /// None unless pinned
+    pub transaction: Option<TransactionInfo>,
+}
+
+/**
+ * The live sessions, by session id. The lock is only held to look a session up or to add or
+ * remove one, never across a query, so commands wait on each other for a moment at most instead
+ * of failing. Connections are cloned out of the registry, and clones share their pool and tunnel.
+ */
+#[derive(Default)]
+pub struct Sessions {
+    sessions: RwLock<HashMap<String, Session>>,
+}
+impl Sessions {
+    /**
+     * A panic while the lock was held can't have left a half-made change behind, as every change
+     * is a single insert or remove. So a poisoned lock is used as is.
+     */
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<String, Session>> {
+        self.sessions.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<String, Session>> {
+        self.sessions
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /**
+     * Binds the connection to the window, returning the id of the new session.
+     */
+    pub fn insert(&self, window_label: &str, title: &str, connection: Connection) -> String {
+        let session_id = crate::uuidv4!();
+        self.write().insert(
+            session_id.clone(),
+            Session {
+                window_label: window_label.to_string(),
+                title: title.to_string(),
+                connection: connection,
+                connected_at: Local::now(),
+            },
+        );
+        session_id
+    }
+
+    /**
+     * Sessions of other windows can't be reached.
+     */
+    pub fn get(&self, window_label: &str, session_id: &str) -> Result<Connection, AppError> {
+        match self.read().get(session_id) {
+            Some(session) if session.window_label == window_label => Ok(session.connection.clone()),
+            _ => Err(not_connected()),
+        }
+    }
+
+    pub fn remove(&self, window_label: &str, session_id: &str) -> Result<Connection, AppError> {
+        let mut sessions = self.write();
+        match sessions.get(session_id) {
+            Some(session) if session.window_label == window_label => (),
+            _ => return Err(not_connected()),
+        }
+        sessions
+            .remove(session_id)
+            .map(|session| session.connection)
+            .ok_or_else(not_connected)
+    }
+
+    /**
+     * Unbinds every session of the window, returning their ids and connections.
+     */
+    pub fn remove_window(&self, window_label: &str) -> Vec<(String, Connection)> {
+        let mut sessions = self.write();
+        let session_ids: Vec<String> = sessions
+            .iter()
+            .filter(|(_, session)| session.window_label == window_label)
+            .map(|(session_id, _)| session_id.clone())
+            .collect();
+        session_ids
+            .into_iter()
+            .filter_map(|session_id| {
+                let session = sessions.remove(&session_id)?;
+                Some((session_id, session.connection))
+            })
+            .collect()
+    }
+
+    /**
+     * Every live session, of every window, oldest first.
+     */
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let now = Local::now();
+        let mut infos: Vec<SessionInfo> = self
+            .read()
+            .iter()
+            .map(|(session_id, session)| SessionInfo {
+                session_id: session_id.clone(),
+                window_label: session.window_label.clone(),
+                title: session.title.clone(),
+                connected_at: session.connected_at,
+                age_secs: (now - session.connected_at).num_seconds(),
+                tunnel: session.connection.tunnel().map(|tunnel| tunnel.status()),
+                pinned: session.connection.is_pinned(),
+                transaction: session.connection.transaction_info(),
+            })
+            .collect();
+        infos.sort_by_key(|info| info.connected_at);
+        infos
+    }
+
+    pub fn count(&self, window_label: &str) -> usize {
+        self.read()
+            .values()
+            .filter(|session| session.window_label == window_label)
+            .count()
+    }
+}
+
+fn not_connected() -> AppError {
+    AppError::new(
+        ErrorKind::NotConnected,
+        "The session is not connected in this window!",
+    )
+}
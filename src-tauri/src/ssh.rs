@@ -1,20 +1,73 @@
 #![allow(unreachable_code)]
 
+use crate::error::ErrorKind;
 use crate::AppError;
 use anyhow::{Error, Result};
 use async_trait::async_trait;
 use client::Handler;
+use futures::future::BoxFuture;
+use russh::client::KeyboardInteractiveAuthResponse;
 use russh::{client, Disconnect};
+#[cfg(unix)]
+use russh_keys::agent::client::AgentClient;
+use russh_keys::known_hosts::{known_host_keys_path, learn_known_hosts_path};
 use russh_keys::{key, load_secret_key};
+use serde::Serialize;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
 use std::{net::Ipv4Addr, sync::Arc};
 use tokio::net::TcpListener;
 use tokio::select;
+use tokio::sync::watch;
+use tokio::task::{JoinHandle, JoinSet};
+
+/// seconds between keepalives when the frontend doesn't say
+const DEFAULT_KEEPALIVE_INTERVAL: u64 = 30;
+
+/// unanswered keepalives after which the session is considered dead
+const KEEPALIVE_MAX: usize = 3;
+
+/// how often the tunnel checks whether its sessions are still alive
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// a dead tunnel is given up on after this many failed reconnects
+const MAX_RECONNECT_ATTEMPTS: usize = 10;
+
+/// the delay between reconnects doubles up to this
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// servers that keep prompting past this many rounds are given up on
+const MAX_KEYBOARD_INTERACTIVE_ROUNDS: usize = 10;
+
+/// a reconnect that takes longer than this counts as failed
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(30);
 
 // @see https://stackoverflow.com/questions/79137536/how-to-create-an-ssh-tunnel-with-russh-that-supports-multiple-connections
 
-#[derive(Clone, Debug, Default, serde::Deserialize)]
-pub struct SshOpts {
+/**
+ * How to authenticate with the SSH server.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize, Serialize)]
+pub enum SshAuth {
+    /// the keyfile if one is set (the password being its passphrase), the password otherwise
+    #[default]
+    Credentials,
+    /// each identity of the running ssh-agent in turn
+    Agent,
+    /// the server's prompts (e.g. password and OTP) are answered by the user, although the
+    /// password is filled in if one is set
+    KeyboardInteractive,
+}
+
+/**
+ * A single SSH server on the way to the database.
+ */
+#[derive(Clone, Debug, Default, serde::Deserialize, Serialize)]
+pub struct SshHop {
+    #[serde(default)]
+    pub auth: SshAuth,
     pub host: String,
     pub keyfile: Option<String>,
     pub password: String,
@@ -22,138 +75,756 @@ pub struct SshOpts {
     pub user: String,
 }
 
-pub struct SshHandler {}
+/**
+ * The hops to the database in order, e.g. bastion, then internal jump host. Each hop is reached
+ * through the one before it and the last one connects to the database.
+ */
+#[derive(Clone, Debug, serde::Deserialize, Serialize)]
+pub struct SshOpts {
+    pub hops: Vec<SshHop>,
+    /// seconds between keepalives on every hop, 0 to send none
+    #[serde(default = "default_keepalive_interval")]
+    pub keepalive_interval: u64,
+}
+impl Default for SshOpts {
+    fn default() -> Self {
+        SshOpts {
+            hops: Vec::new(),
+            keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
+        }
+    }
+}
+impl From<SshHop> for SshOpts {
+    fn from(hop: SshHop) -> Self {
+        SshOpts {
+            hops: vec![hop],
+            ..<_>::default()
+        }
+    }
+}
+
+fn default_keepalive_interval() -> u64 {
+    DEFAULT_KEEPALIVE_INTERVAL
+}
+
+/**
+ * Shown to the user when a server presents a key that isn't known yet.
+ */
+#[derive(Serialize, Clone, Debug)]
+pub struct HostKeyInfo {
+    pub host: String,
+    pub port: u16,
+    pub key_type: String,
+    /// e.g. "SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s"
+    pub fingerprint: String,
+}
+
+/**
+ * Asks the user whether to trust an unknown host key. Resolves to true to trust it.
+ */
+pub type HostKeyPrompt = Arc<dyn Fn(HostKeyInfo) -> BoxFuture<'static, bool> + Send + Sync>;
+
+/**
+ * Decides which host keys to trust. A key is trusted if it is listed in one of the known_hosts
+ * files (hashed host names included) or in the trust store. Unknown keys are trusted if the
+ * prompt says so, and are then added to the trust store. A key that differs from the one on
+ * file for the same host and key type is always rejected.
+ */
+#[derive(Clone, Default)]
+pub struct HostKeyVerifier {
+    /// read only, e.g. ~/.ssh/known_hosts
+    pub known_hosts: Vec<PathBuf>,
+    /// the app's own known_hosts file
+    pub trust_store: Option<PathBuf>,
+    /// without a prompt, unknown keys are rejected
+    pub prompt: Option<HostKeyPrompt>,
+}
+impl HostKeyVerifier {
+    async fn verify(&self, host: &str, port: u16, server_key: &key::PublicKey) -> Result<()> {
+        let host_port = format!("{}:{}", host, port);
+        for path in self.known_hosts.iter().chain(self.trust_store.iter()) {
+            if !path.exists() {
+                continue;
+            }
+            let known_keys = known_host_keys_path(host, port, path)?;
+            if known_keys.iter().any(|(_, key)| key == server_key) {
+                return Ok(());
+            }
+            // a host may have one key per type, so only a key of the same type was replaced.
+            if let Some((line, _)) = known_keys
+                .iter()
+                .find(|(_, key)| key.name() == server_key.name())
+            {
+                return Err(AppError::new(ErrorKind::HostKey, format!(
+                    "The {} host key of {} has changed! Someone could be intercepting the connection, \
+                    or the server's key was replaced. If the change is expected, remove line {} of {} and connect again.",
+                    server_key.name(),
+                    host_port,
+                    line,
+                    path.display()
+                ))
+                .into());
+            }
+        }
+
+        let info = HostKeyInfo {
+            host: host.to_string(),
+            port: port,
+            key_type: server_key.name().to_string(),
+            fingerprint: format!("SHA256:{}", server_key.fingerprint()),
+        };
+        let trusted = match &self.prompt {
+            Some(prompt) => prompt(info.clone()).await,
+            None => false,
+        };
+        if !trusted {
+            return Err(AppError::new(
+                ErrorKind::HostKey,
+                format!(
+                    "The {} host key of {} ({}) is not trusted",
+                    info.key_type, host_port, info.fingerprint
+                ),
+            )
+            .into());
+        }
+
+        if let Some(path) = &self.trust_store {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            learn_known_hosts_path(host, port, server_key, path)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * A round of keyboard-interactive prompts for the user to answer, e.g. for a one-time password.
+ */
+#[derive(Serialize, Clone, Debug)]
+pub struct KeyboardInteractiveRequest {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub name: String,
+    pub instructions: String,
+    pub prompts: Vec<KeyboardInteractivePrompt>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct KeyboardInteractivePrompt {
+    pub prompt: String,
+    /// whether the answer may be shown while typed
+    pub echo: bool,
+}
+
+/**
+ * Resolves with one answer per prompt, or None if the user cancelled.
+ */
+pub type KeyboardInteractiveHandler = Arc<
+    dyn Fn(KeyboardInteractiveRequest) -> BoxFuture<'static, Option<Vec<String>>> + Send + Sync,
+>;
+
+/**
+ * Sent whenever a tunnel loses its session, reconnects or gives up.
+ */
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum TunnelStatus {
+    Connected,
+    Reconnecting { attempt: usize, error: String },
+    Failed { error: String },
+    Closed,
+}
+
+pub type TunnelStatusListener = Arc<dyn Fn(TunnelStatus) + Send + Sync>;
+
+/**
+ * Whatever the tunnel needs from the app around the connection itself.
+ */
+#[derive(Clone, Default)]
+pub struct SshContext {
+    pub host_keys: HostKeyVerifier,
+    /// the ssh-agent's socket, SSH_AUTH_SOCK if not set
+    pub agent_socket: Option<PathBuf>,
+    pub on_status: Option<TunnelStatusListener>,
+    /// without one, keyboard-interactive authentication fails on the first prompt it can't answer
+    pub keyboard_interactive: Option<KeyboardInteractiveHandler>,
+}
+impl SshContext {
+    fn status(&self, status: TunnelStatus) {
+        if let Some(on_status) = &self.on_status {
+            on_status(status);
+        }
+    }
+}
+
+pub struct SshHandler {
+    host: String,
+    port: u16,
+    host_keys: HostKeyVerifier,
+}
 #[async_trait]
 impl Handler for SshHandler {
     type Error = Error;
-    async fn check_server_key(&mut self, _: &key::PublicKey) -> Result<bool, Self::Error> {
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &key::PublicKey,
+    ) -> Result<bool, Self::Error> {
+        self.host_keys
+            .verify(&self.host, self.port, server_public_key)
+            .await?;
         Ok(true)
     }
 }
 
-async fn connect(ssh_opts: SshOpts) -> Result<client::Handle<SshHandler>, Error> {
-    let config = client::Config {
-        // custom configs go here. possibly add in from ssh_opts
+/**
+ * Connects to each hop in turn, every one through a direct-tcpip channel of the one before.
+ * The sessions are returned in the same order, so the last one reaches the target. The earlier
+ * ones carry its traffic and have to be kept open as long as it is.
+ */
+async fn connect_chain(
+    ssh_opts: SshOpts,
+    context: SshContext,
+) -> Result<Vec<client::Handle<SshHandler>>, Error> {
+    if ssh_opts.hops.is_empty() {
+        return Err(Error::msg("No SSH host to connect through"));
+    }
+    // a session that misses KEEPALIVE_MAX keepalives in a row is closed, so it is detected as lost.
+    let config = Arc::new(client::Config {
+        keepalive_interval: match ssh_opts.keepalive_interval {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        },
+        keepalive_max: KEEPALIVE_MAX,
         ..<_>::default()
+    });
+    let num_hops = ssh_opts.hops.len();
+    let mut sessions: Vec<client::Handle<SshHandler>> = Vec::new();
+    for (i, hop) in ssh_opts.hops.into_iter().enumerate() {
+        let host_port = format!("{}:{}", hop.host, hop.port);
+        let session = connect(hop, config.clone(), context.clone(), sessions.last())
+            .await
+            .map_err(|e| match num_hops {
+                1 => e,
+                // as context, so the error raised by the hop can still be told apart.
+                _ => {
+                    let message = format!("Hop {} of {} ({}): {}", i + 1, num_hops, host_port, e);
+                    e.context(message)
+                }
+            })?;
+        sessions.push(session);
+    }
+    Ok(sessions)
+}
+
+/**
+ * Connects to the hop directly, or through the session of the previous hop.
+ */
+async fn connect(
+    ssh_opts: SshHop,
+    config: Arc<client::Config>,
+    context: SshContext,
+    via: Option<&client::Handle<SshHandler>>,
+) -> Result<client::Handle<SshHandler>, Error> {
+    let handler = SshHandler {
+        host: ssh_opts.host.clone(),
+        port: ssh_opts.port,
+        host_keys: context.host_keys,
+    };
+
+    let mut ssh_client = match via {
+        Some(previous) => {
+            let channel = previous
+                .channel_open_direct_tcpip(
+                    ssh_opts.host.clone(),
+                    ssh_opts.port.into(),
+                    Ipv4Addr::LOCALHOST.to_string(),
+                    0,
+                )
+                .await?;
+            client::connect_stream(config, channel.into_stream(), handler).await?
+        }
+        None => {
+            client::connect(
+                config,
+                format!("{}:{}", ssh_opts.host, ssh_opts.port),
+                handler,
+            )
+            .await?
+        }
     };
+    log::info!("Connected to {}:{}", ssh_opts.host, ssh_opts.port);
 
-    let mut ssh_client = client::connect(
-        Arc::new(config),
-        format!("{}:{}", ssh_opts.host, ssh_opts.port),
-        SshHandler {},
-    )
-    .await?;
-    println!(
-        "DEBUG: Connected to jump host {}:{}",
-        ssh_opts.host, ssh_opts.port
-    );
-
-    // Create a session to the jump host
     let ssh_user = ssh_opts.user.clone();
     let ssh_password = ssh_opts.password.clone();
-    let password_option = if ssh_password.len() > 0 {
+    let password_option = if !ssh_password.is_empty() {
         Some(ssh_password.as_str())
     } else {
         None
     };
 
-    let is_authenticated: bool = match ssh_opts.keyfile.clone() {
-        Some(ssh_key_file) => {
-            println!("DEBUG: Authenticating with keyfile");
+    let is_authenticated: bool = match (ssh_opts.auth, ssh_opts.keyfile.clone()) {
+        (SshAuth::Agent, _) => {
+            authenticate_with_agent(&mut ssh_client, ssh_user, context.agent_socket).await?
+        }
+        (SshAuth::KeyboardInteractive, _) => {
+            authenticate_keyboard_interactive(
+                &mut ssh_client,
+                &ssh_opts,
+                context.keyboard_interactive,
+            )
+            .await?
+        }
+        (SshAuth::Credentials, Some(ssh_key_file)) => {
+            log::debug!("Authenticating as {} with {}", ssh_user, ssh_key_file);
             let key_file = load_secret_key(ssh_key_file, password_option)?;
             ssh_client
                 .authenticate_publickey(ssh_user, Arc::new(key_file))
                 .await
                 .map_err(|e| {
-                    eprintln!("Failed to authenticate with user and key: {}", e);
-                    AppError::from("Failed to authenticate with the SSH Server")
+                    log::warn!("Failed to authenticate with user and key: {}", e);
+                    AppError::new(
+                        ErrorKind::Auth,
+                        "Failed to authenticate with the SSH Server",
+                    )
                 })?
         }
-        None => {
-            println!(
-                "DEBUG: Authenticating with password {}:{}",
-                ssh_user, ssh_password
-            );
+        (SshAuth::Credentials, None) => {
+            log::debug!("Authenticating as {} with a password", ssh_user);
             ssh_client
                 .authenticate_password(ssh_user, ssh_password)
                 .await
                 .map_err(|e| {
-                    eprintln!("Failed to authenticate with user and password: {}", e);
-                    AppError::from("Failed to authenticate with the SSH Server")
+                    log::warn!("Failed to authenticate with user and password: {}", e);
+                    AppError::new(
+                        ErrorKind::Auth,
+                        "Failed to authenticate with the SSH Server",
+                    )
                 })?
         }
     };
 
     if !is_authenticated {
-        return Err(Error::msg("Failed to authenticate with the jump host"));
+        return Err(
+            AppError::new(ErrorKind::Auth, "Failed to authenticate with the jump host").into(),
+        );
     }
 
     Ok(ssh_client)
 }
 
+/**
+ * Answers the server's prompts until it accepts or rejects them. The password, if one is set,
+ * answers the first password prompt, so the user is only asked for the rest (e.g. the OTP).
+ */
+async fn authenticate_keyboard_interactive(
+    ssh_client: &mut client::Handle<SshHandler>,
+    ssh_opts: &SshHop,
+    handler: Option<KeyboardInteractiveHandler>,
+) -> Result<bool, Error> {
+    let mut password = Some(ssh_opts.password.clone()).filter(|password| !password.is_empty());
+    let mut response = ssh_client
+        .authenticate_keyboard_interactive_start(ssh_opts.user.clone(), None)
+        .await?;
+
+    for _ in 0..MAX_KEYBOARD_INTERACTIVE_ROUNDS {
+        let (name, instructions, prompts) = match response {
+            KeyboardInteractiveAuthResponse::Success => return Ok(true),
+            KeyboardInteractiveAuthResponse::Failure => return Ok(false),
+            KeyboardInteractiveAuthResponse::InfoRequest {
+                name,
+                instructions,
+                prompts,
+            } => (name, instructions, prompts),
+        };
+
+        let mut answers: Vec<Option<String>> = Vec::new();
+        for prompt in prompts.iter() {
+            let is_password = !prompt.echo && prompt.prompt.to_lowercase().contains("password");
+            answers.push(if is_password { password.take() } else { None });
+        }
+
+        let unanswered: Vec<KeyboardInteractivePrompt> = prompts
+            .iter()
+            .zip(answers.iter())
+            .filter(|(_, answer)| answer.is_none())
+            .map(|(prompt, _)| KeyboardInteractivePrompt {
+                prompt: prompt.prompt.clone(),
+                echo: prompt.echo,
+            })
+            .collect();
+        if !unanswered.is_empty() {
+            let request = KeyboardInteractiveRequest {
+                host: ssh_opts.host.clone(),
+                port: ssh_opts.port,
+                user: ssh_opts.user.clone(),
+                name: name,
+                instructions: instructions,
+                prompts: unanswered,
+            };
+            let replies = match &handler {
+                Some(handler) => handler(request).await,
+                None => None,
+            };
+            let mut replies = replies
+                .ok_or_else(|| {
+                    AppError::new(
+                        ErrorKind::Cancelled,
+                        "Keyboard-interactive authentication was cancelled",
+                    )
+                })?
+                .into_iter();
+            for answer in answers.iter_mut().filter(|answer| answer.is_none()) {
+                *answer = Some(replies.next().unwrap_or_default());
+            }
+        }
+
+        response = ssh_client
+            .authenticate_keyboard_interactive_respond(
+                answers.into_iter().map(Option::unwrap_or_default).collect(),
+            )
+            .await?;
+    }
+    Err(Error::msg(
+        "The SSH server kept asking for keyboard-interactive answers",
+    ))
+}
+
+/**
+ * Offers every identity of the agent until the server accepts one.
+ */
+#[cfg(unix)]
+async fn authenticate_with_agent(
+    ssh_client: &mut client::Handle<SshHandler>,
+    ssh_user: String,
+    agent_socket: Option<PathBuf>,
+) -> Result<bool, Error> {
+    let agent_socket = agent_socket.or(std::env::var_os("SSH_AUTH_SOCK").map(PathBuf::from));
+    let agent_socket = match agent_socket {
+        Some(agent_socket) => agent_socket,
+        None => {
+            return Err(Error::msg(
+                "No ssh-agent is running (SSH_AUTH_SOCK is not set)",
+            ))
+        }
+    };
+    let mut agent = AgentClient::connect_uds(&agent_socket).await.map_err(|e| {
+        Error::msg(format!(
+            "Unable to reach the ssh-agent at {}: {}",
+            agent_socket.display(),
+            e
+        ))
+    })?;
+
+    let identities = agent.request_identities().await?;
+    if identities.is_empty() {
+        return Err(Error::msg("The ssh-agent has no identities"));
+    }
+    for key in identities {
+        let (returned, result) = ssh_client
+            .authenticate_future(ssh_user.clone(), key, agent)
+            .await;
+        agent = returned;
+        // the agent may refuse to sign with a key, e.g. when its confirmation is declined.
+        if let Ok(true) = result {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(not(unix))]
+async fn authenticate_with_agent(
+    _ssh_client: &mut client::Handle<SshHandler>,
+    _ssh_user: String,
+    _agent_socket: Option<PathBuf>,
+) -> Result<bool, Error> {
+    Err(Error::msg(
+        "ssh-agent authentication is not supported on this platform",
+    ))
+}
+
+/**
+ * Forwards a local port to the target through the SSH hops. The tunnel is closed with close(),
+ * or in the background once it is dropped.
+ */
+#[derive(Debug)]
+pub struct Tunnel {
+    local_addr: SocketAddr,
+    shutdown: watch::Sender<bool>,
+    task: Mutex<Option<JoinHandle<()>>>,
+    /// why the tunnel stopped forwarding, if it failed
+    error: Arc<Mutex<Option<String>>>,
+    /// the last status reported to the context
+    status: Arc<Mutex<TunnelStatus>>,
+}
+impl Tunnel {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /**
+     * The error the tunnel failed with, e.g. when the SSH session was dropped by the server.
+     */
+    pub fn error(&self) -> Option<String> {
+        self.error.lock().ok().and_then(|error| error.clone())
+    }
+
+    pub fn status(&self) -> TunnelStatus {
+        self.status
+            .lock()
+            .map(|status| status.clone())
+            .unwrap_or(TunnelStatus::Closed)
+    }
+
+    /**
+     * Stops accepting connections, closes every forwarded channel and then the sessions, last hop
+     * first. Resolves with the error the tunnel failed with before, if any.
+     */
+    pub async fn close(&self) -> Result<()> {
+        self.shutdown.send_replace(true);
+        let task = self.task.lock().ok().and_then(|mut task| task.take());
+        if let Some(task) = task {
+            task.await?;
+        }
+        match self.error() {
+            Some(error) => Err(Error::msg(error)),
+            None => Ok(()),
+        }
+    }
+}
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        self.shutdown.send_replace(true);
+    }
+}
+
+/**
+ * Tunnels a local port to the target. Should the sessions be lost (e.g. the laptop slept or a NAT
+ * dropped them), they are re-established behind the same local port.
+ */
 pub async fn jump(
     ssh_opts: SshOpts,
+    context: SshContext,
     target_host: String,
     target_port: u32,
-) -> Result<SocketAddr, Error> {
-    let ssh_client = connect(ssh_opts).await?;
+) -> Result<Tunnel, Error> {
+    let sessions = connect_chain(ssh_opts.clone(), context.clone()).await?;
+
+    // the status is kept for the tunnel to report, on top of being passed on.
+    let status = Arc::new(Mutex::new(TunnelStatus::Connected));
+    let recorded_status = status.clone();
+    let on_status = context.on_status.clone();
+    let context = SshContext {
+        on_status: Some(Arc::new(move |status: TunnelStatus| {
+            if let Ok(mut recorded) = recorded_status.lock() {
+                *recorded = status.clone();
+            }
+            if let Some(on_status) = &on_status {
+                on_status(status);
+            }
+        })),
+        ..context
+    };
 
     let local_listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
     let local_addr = local_listener.local_addr()?;
-    let local_port = local_addr.port();
 
-    println!("DEBUG: Local listener bound to port {}", local_port);
-
-    let (tx, rx) = tokio::sync::watch::channel::<u8>(1);
-
-    tokio::spawn(async move {
-        loop {
-            let mut rx_clone = rx.clone();
-
-            if let Ok((mut local_stream, _)) = local_listener.accept().await {
-                let ssh_channel = ssh_client
-                    .channel_open_direct_tcpip(
-                        target_host.clone(),
-                        target_port.into(),
-                        local_addr.ip().to_string(),
-                        local_port as u32,
-                    )
-                    .await?;
-                println!("DEBUG: Channel open to target host");
-
-                let mut remote_stream = ssh_channel.into_stream();
-
-                tokio::spawn(async move {
-                    select! {
-                        result = tokio::io::copy_bidirectional_with_sizes(&mut local_stream, &mut remote_stream, 255, 8 * 1024) => {
-                            if let Err(e) = result {
-                                eprintln!("Error during bidirectional copy: {}", e);
-                            }
-                        }
-                        _ = rx_clone.changed() => {
-                            println!("Received close signal");
-                        }
-                    }
+    let (shutdown, shutdown_rx) = watch::channel(false);
+    let error: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+    let task_error = error.clone();
+    let task = tokio::spawn(async move {
+        let target = (target_host, target_port);
+        let result = run(
+            &local_listener,
+            sessions,
+            &ssh_opts,
+            &context,
+            &target,
+            shutdown_rx,
+        )
+        .await;
+        drop(local_listener);
+        match result {
+            Ok(()) => context.status(TunnelStatus::Closed),
+            Err(why) => {
+                log::error!("SSH tunnel error: {}", why);
+                if let Ok(mut error) = task_error.lock() {
+                    *error = Some(why.to_string());
+                }
+                context.status(TunnelStatus::Failed {
+                    error: why.to_string(),
                 });
             }
-            if rx.has_changed()? {
-                disconnect(&tx, &ssh_client).await?;
-            }
         }
-        drop(local_listener);
-        Ok::<(), Error>(())
     });
 
-    Ok(local_addr.clone())
+    Ok(Tunnel {
+        local_addr: local_addr,
+        shutdown: shutdown,
+        task: Mutex::new(Some(task)),
+        error: error,
+        status: status,
+    })
 }
 
-async fn disconnect(
-    tx: &tokio::sync::watch::Sender<u8>,
-    ssh_client: &client::Handle<SshHandler>,
+/**
+ * Forwards connections until the tunnel is shut down, reconnecting whenever the sessions are lost.
+ */
+async fn run(
+    local_listener: &TcpListener,
+    mut sessions: Vec<client::Handle<SshHandler>>,
+    ssh_opts: &SshOpts,
+    context: &SshContext,
+    target: &(String, u32),
+    mut shutdown: watch::Receiver<bool>,
 ) -> Result<()> {
-    tx.send(0)?;
-    ssh_client
-        .disconnect(Disconnect::ByApplication, "Disconnected by User", "none")
-        .await?;
-    Ok(())
+    loop {
+        let forwarded = forward(local_listener, &sessions, target, &mut shutdown).await;
+        disconnect(&sessions).await;
+        let lost = match forwarded? {
+            Some(lost) => lost,
+            None => return Ok(()),
+        };
+        log::warn!("SSH tunnel lost its session: {}", lost);
+        sessions = match reconnect(ssh_opts, context, lost, &mut shutdown).await? {
+            Some(sessions) => sessions,
+            None => return Ok(()),
+        };
+        context.status(TunnelStatus::Connected);
+    }
+}
+
+/**
+ * Opens a direct-tcpip channel on the last hop for every accepted connection. Resolves with None
+ * once the tunnel is shut down, or with the reason the sessions were lost. Forwarded channels are
+ * closed either way.
+ */
+async fn forward(
+    local_listener: &TcpListener,
+    sessions: &[client::Handle<SshHandler>],
+    (target_host, target_port): &(String, u32),
+    shutdown: &mut watch::Receiver<bool>,
+) -> Result<Option<String>> {
+    let ssh_client = &sessions[sessions.len() - 1];
+    let local_addr = local_listener.local_addr()?;
+    let mut forwards: JoinSet<()> = JoinSet::new();
+    let mut session_check = tokio::time::interval(SESSION_CHECK_INTERVAL);
+
+    let lost = loop {
+        let mut local_stream = select! {
+            _ = shutdown.wait_for(|closed| *closed) => break None,
+            _ = session_check.tick() => {
+                if sessions.iter().any(|session| session.is_closed()) {
+                    break Some("The SSH session was closed".to_string());
+                }
+                continue;
+            }
+            accepted = local_listener.accept() => accepted?.0,
+        };
+        // forget the connections that were closed in the meantime.
+        while forwards.try_join_next().is_some() {}
+
+        let ssh_channel = match ssh_client
+            .channel_open_direct_tcpip(
+                target_host.clone(),
+                *target_port,
+                local_addr.ip().to_string(),
+                local_addr.port() as u32,
+            )
+            .await
+        {
+            Ok(ssh_channel) => ssh_channel,
+            Err(why) if sessions.iter().any(|session| session.is_closed()) => {
+                break Some(why.to_string());
+            }
+            Err(why) => {
+                // only this connection fails, e.g. when the target refuses it.
+                log::warn!(
+                    "Unable to open a channel to {}:{}: {}",
+                    target_host,
+                    target_port,
+                    why
+                );
+                continue;
+            }
+        };
+
+        let mut remote_stream = ssh_channel.into_stream();
+        forwards.spawn(async move {
+            if let Err(e) = tokio::io::copy_bidirectional_with_sizes(
+                &mut local_stream,
+                &mut remote_stream,
+                255,
+                8 * 1024,
+            )
+            .await
+            {
+                log::debug!("Error during bidirectional copy: {}", e);
+            }
+        });
+    };
+
+    forwards.shutdown().await;
+    Ok(lost)
+}
+
+/**
+ * Connects the hops again, backing off between attempts. Connections to the local port wait in
+ * the listen backlog meanwhile. Resolves with None if the tunnel is shut down before.
+ */
+async fn reconnect(
+    ssh_opts: &SshOpts,
+    context: &SshContext,
+    lost: String,
+    shutdown: &mut watch::Receiver<bool>,
+) -> Result<Option<Vec<client::Handle<SshHandler>>>> {
+    let mut error = lost;
+    let mut delay = Duration::from_secs(1);
+    for attempt in 1..=MAX_RECONNECT_ATTEMPTS {
+        context.status(TunnelStatus::Reconnecting {
+            attempt: attempt,
+            error: error.clone(),
+        });
+        let connecting = tokio::time::timeout(
+            RECONNECT_TIMEOUT,
+            connect_chain(ssh_opts.clone(), context.clone()),
+        );
+        select! {
+            _ = shutdown.wait_for(|closed| *closed) => return Ok(None),
+            connected = connecting => match connected {
+                Ok(Ok(sessions)) => return Ok(Some(sessions)),
+                Ok(Err(why)) => error = why.to_string(),
+                Err(_) => error = "Timed out connecting to the SSH server".to_string(),
+            },
+        }
+        select! {
+            _ = shutdown.wait_for(|closed| *closed) => return Ok(None),
+            _ = tokio::time::sleep(delay) => (),
+        }
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+    Err(Error::msg(format!(
+        "Unable to reconnect after {} attempts: {}",
+        MAX_RECONNECT_ATTEMPTS, error
+    )))
+}
+
+/**
+ * Closes the sessions from the last hop back to the first. Sessions that are already gone are
+ * skipped.
+ */
+async fn disconnect(sessions: &[client::Handle<SshHandler>]) {
+    for ssh_client in sessions.iter().rev() {
+        if ssh_client.is_closed() {
+            continue;
+        }
+        if let Err(e) = ssh_client
+            .disconnect(Disconnect::ByApplication, "Disconnected by User", "none")
+            .await
+        {
+            log::warn!("Unable to close the SSH session: {}", e);
+        }
+    }
 }
@@ -0,0 +1,374 @@
+use crate::ssh::{SshAuth, SshHop, SshOpts};
+use anyhow::{Error, Result};
+use std::path::{Path, PathBuf};
+
+/// Include directives nested deeper than this are ignored, as ssh itself does
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// ProxyJump chains longer than this are assumed to loop
+const MAX_HOPS: usize = 16;
+
+/**
+ * A Host (or Match) section, along with the options set within it. Options set before the
+ * first section apply to every host.
+ */
+#[derive(Debug)]
+struct Section {
+    /// Match sections have none, so they never apply
+    patterns: Vec<String>,
+    /// keywords are lowercased
+    options: Vec<(String, String)>,
+}
+impl Section {
+    /**
+     * A section applies if any of its patterns match the host, unless a negated one does.
+     */
+    fn applies_to(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        let mut matched = false;
+        for pattern in self.patterns.iter() {
+            match pattern.strip_prefix('!') {
+                Some(negated) if wildcard_match(&negated.to_lowercase(), &host) => return false,
+                Some(_) => (),
+                None => matched = matched || wildcard_match(&pattern.to_lowercase(), &host),
+            }
+        }
+        matched
+    }
+}
+
+/**
+ * The subset of ~/.ssh/config that matters for tunnelling: HostName, User, Port, IdentityFile and
+ * ProxyJump. Include directives are followed, Match sections are skipped.
+ */
+#[derive(Debug, Default)]
+pub struct SshConfig {
+    sections: Vec<Section>,
+    home_dir: Option<PathBuf>,
+}
+impl SshConfig {
+    /**
+     * A missing file is an empty config. Relative includes are resolved against ~/.ssh.
+     */
+    pub fn load(path: &Path, home_dir: Option<PathBuf>) -> Result<SshConfig> {
+        let mut config = SshConfig::new(home_dir);
+        if path.exists() {
+            config.read_file(path, 0, 0)?;
+        }
+        Ok(config)
+    }
+
+    pub fn parse(text: &str, home_dir: Option<PathBuf>) -> Result<SshConfig> {
+        let mut config = SshConfig::new(home_dir);
+        config.read(text, 0, 0)?;
+        Ok(config)
+    }
+
+    fn new(home_dir: Option<PathBuf>) -> SshConfig {
+        SshConfig {
+            sections: vec![Section {
+                patterns: vec!["*".to_string()],
+                options: Vec::new(),
+            }],
+            home_dir: home_dir,
+        }
+    }
+
+    fn read_file(&mut self, path: &Path, depth: usize, section: usize) -> Result<()> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::msg(format!("Unable to read {}: {}", path.display(), e)))?;
+        self.read(&text, depth, section)
+    }
+
+    /**
+     * Options go to the index of the section they are in, which for those before the first Host
+     * of an included file is the section of the Include, and stays so after it.
+     */
+    fn read(&mut self, text: &str, depth: usize, mut section: usize) -> Result<()> {
+        for line in text.lines() {
+            let (keyword, args) = match split_line(line) {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+            match keyword.as_str() {
+                "host" => {
+                    self.sections.push(Section {
+                        patterns: args,
+                        options: Vec::new(),
+                    });
+                    section = self.sections.len() - 1;
+                }
+                "match" => {
+                    self.sections.push(Section {
+                        patterns: Vec::new(),
+                        options: Vec::new(),
+                    });
+                    section = self.sections.len() - 1;
+                }
+                "include" if depth < MAX_INCLUDE_DEPTH => {
+                    let paths: Vec<PathBuf> = args
+                        .iter()
+                        .flat_map(|arg| self.include_paths(arg))
+                        .collect();
+                    for path in paths {
+                        self.read_file(&path, depth + 1, section)?;
+                    }
+                }
+                _ => self.sections[section]
+                    .options
+                    .push((keyword, args.join(" "))),
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Expands ~ and wildcards in the file name, e.g. "*.conf" in config.d.
+     */
+    fn include_paths(&self, arg: &str) -> Vec<PathBuf> {
+        let path = self.expand_home(arg);
+        let path = match (path.is_relative(), &self.home_dir) {
+            (true, Some(home)) => home.join(".ssh").join(path),
+            _ => path,
+        };
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if !file_name.contains(['*', '?']) {
+            return vec![path];
+        }
+
+        let mut paths: Vec<PathBuf> = match path.parent().map(std::fs::read_dir) {
+            Some(Ok(entries)) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|entry| {
+                    entry.is_file()
+                        && entry
+                            .file_name()
+                            .map(|name| wildcard_match(&file_name, &name.to_string_lossy()))
+                            .unwrap_or(false)
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        paths.sort();
+        paths
+    }
+
+    fn expand_home(&self, path: &str) -> PathBuf {
+        match (path.strip_prefix("~/"), &self.home_dir) {
+            (Some(rest), Some(home)) => home.join(rest),
+            _ => PathBuf::from(path),
+        }
+    }
+
+    /**
+     * The aliases defined by Host lines, in order. Patterns with wildcards aren't aliases.
+     */
+    pub fn aliases(&self) -> Vec<String> {
+        let mut aliases: Vec<String> = Vec::new();
+        for section in self.sections.iter() {
+            for pattern in section.patterns.iter() {
+                if !pattern.contains(['*', '?', '!']) && !aliases.contains(pattern) {
+                    aliases.push(pattern.clone());
+                }
+            }
+        }
+        aliases
+    }
+
+    /**
+     * Like ssh, the first value found for an option wins.
+     */
+    fn option(&self, host: &str, keyword: &str) -> Option<String> {
+        self.sections
+            .iter()
+            .filter(|section| section.applies_to(host))
+            .flat_map(|section| section.options.iter())
+            .find(|(option, _)| option == keyword)
+            .map(|(_, value)| value.clone())
+    }
+
+    /**
+     * Resolves an alias into the hops to reach it: its ProxyJump hosts (the first one resolved in
+     * turn, with its own ProxyJump) followed by the host itself.
+     */
+    pub fn resolve(&self, alias: &str) -> Result<SshOpts> {
+        let mut hops: Vec<SshHop> = Vec::new();
+        self.resolve_hops(alias, None, None, &mut hops, 0)?;
+        Ok(SshOpts {
+            hops: hops,
+            ..<_>::default()
+        })
+    }
+
+    fn resolve_hops(
+        &self,
+        alias: &str,
+        user: Option<String>,
+        port: Option<u16>,
+        hops: &mut Vec<SshHop>,
+        depth: usize,
+    ) -> Result<()> {
+        if depth >= MAX_HOPS || hops.len() >= MAX_HOPS {
+            return Err(Error::msg(format!(
+                "The ProxyJump chain of {} is too long, or loops",
+                alias
+            )));
+        }
+
+        let proxy_jump = self
+            .option(alias, "proxyjump")
+            .filter(|jumps| !jumps.eq_ignore_ascii_case("none"));
+        for (index, jump) in proxy_jump
+            .iter()
+            .flat_map(|jumps| jumps.split(','))
+            .enumerate()
+        {
+            let (jump_user, jump_host, jump_port) = parse_jump(jump.trim())?;
+            // as with `ssh -J`, the hosts after the first are reached through the ones before
+            // them, never through their own ProxyJump.
+            match index {
+                0 => self.resolve_hops(&jump_host, jump_user, jump_port, hops, depth + 1)?,
+                _ => hops.push(self.hop(&jump_host, jump_user, jump_port)?),
+            }
+        }
+        hops.push(self.hop(alias, user, port)?);
+        Ok(())
+    }
+
+    /**
+     * A single host, as reached without going through its ProxyJump.
+     */
+    fn hop(&self, alias: &str, user: Option<String>, port: Option<u16>) -> Result<SshHop> {
+        let host = self
+            .option(alias, "hostname")
+            .map(|hostname| hostname.replace("%h", alias))
+            .unwrap_or(alias.to_string());
+        let port = match (port, self.option(alias, "port")) {
+            (Some(port), _) => port,
+            (None, Some(port)) => port
+                .parse()
+                .map_err(|_| Error::msg(format!("Invalid Port {} for {}", port, alias)))?,
+            (None, None) => 22,
+        };
+        let user = user
+            .or_else(|| self.option(alias, "user"))
+            .or_else(|| {
+                users::get_current_username().map(|name| name.to_string_lossy().to_string())
+            })
+            .unwrap_or_default();
+        let keyfile = self
+            .option(alias, "identityfile")
+            .map(|path| self.expand_home(&path).to_string_lossy().to_string());
+
+        // without an identity file, a running agent is the most likely way in.
+        let auth = match &keyfile {
+            None if std::env::var_os("SSH_AUTH_SOCK").is_some() => SshAuth::Agent,
+            _ => SshAuth::Credentials,
+        };
+        Ok(SshHop {
+            auth: auth,
+            host: host,
+            keyfile: keyfile,
+            password: String::new(),
+            port: port,
+            user: user,
+        })
+    }
+}
+
+/**
+ * Splits a line into its lowercased keyword and arguments. Keywords are separated from their
+ * arguments by whitespace or "=", and arguments may be quoted.
+ */
+fn split_line(line: &str) -> Option<(String, Vec<String>)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let keyword = line[..end].to_lowercase();
+    let rest = line[end..].trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+
+    let mut args: Vec<String> = Vec::new();
+    let mut arg = String::new();
+    let mut quoted = false;
+    for c in rest.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !arg.is_empty() {
+                    args.push(std::mem::take(&mut arg));
+                }
+            }
+            c => arg.push(c),
+        }
+    }
+    if !arg.is_empty() {
+        args.push(arg);
+    }
+    Some((keyword, args))
+}
+
+/**
+ * e.g. "user@bastion:2222", where only the host is required.
+ */
+fn parse_jump(jump: &str) -> Result<(Option<String>, String, Option<u16>)> {
+    let jump = jump.strip_prefix("ssh://").unwrap_or(jump);
+    let (user, host_port) = match jump.rsplit_once('@') {
+        Some((user, host_port)) => (Some(user.to_string()), host_port),
+        None => (None, jump),
+    };
+    let (host, port) = match host_port.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            Some(
+                port.parse::<u16>()
+                    .map_err(|_| Error::msg(format!("Invalid ProxyJump host {}", jump)))?,
+            ),
+        ),
+        None => (host_port, None),
+    };
+    if host.is_empty() {
+        return Err(Error::msg(format!("Invalid ProxyJump host {}", jump)));
+    }
+    Ok((user, host.to_string(), port))
+}
+
+/**
+ * Matches ssh's patterns, where * is any number of characters and ? exactly one.
+ */
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // where the last * was seen, and how much of the text it covers so far
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, covered)) => {
+                    p = star + 1;
+                    t = covered + 1;
+                    backtrack = Some((star, covered + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
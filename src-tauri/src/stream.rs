@@ -0,0 +1,170 @@
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use tauri::ipc::Channel;
+
+use crate::adapters::{JsonValue, QueryColumn, QuerySink, StatementInfo};
+use crate::AppError;
+
+/// rows per batch when the frontend doesn't ask for a size
+pub const DEFAULT_BATCH_SIZE: usize = 500;
+
+/// a partial batch is flushed anyway once it is this old, so slow queries still show progress
+const MAX_BATCH_DELAY: Duration = Duration::from_millis(250);
+
+/**
+ * Sent over the channel passed to adapter_query_stream. For every statement, in order:
+ * one "columns" event, any number of "rows" events, then an "end" event.
+ */
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum QueryStreamEvent {
+    Columns {
+        result_index: usize,
+        columns: Vec<QueryColumn>,
+    },
+    Rows {
+        result_index: usize,
+        rows: Vec<Vec<JsonValue>>,
+        /// rows of this statement sent so far, this batch included
+        num_rows: usize,
+        /// since the query started
+        elapsed_ms: u128,
+    },
+    End {
+        result_index: usize,
+        num_rows: usize,
+        #[serde(flatten)]
+        info: StatementInfo,
+        /// since the statement started, like QueryResult::elapsed_ms
+        elapsed_ms: u128,
+    },
+}
+
+/**
+ * What adapter_query_stream resolves to once every batch was sent.
+ */
+#[derive(Serialize, Debug)]
+pub struct QueryStreamSummary {
+    pub elapsed_ms: String,
+    pub first_batch_ms: Option<String>,
+    pub num_rows: String,
+    pub num_results: usize,
+}
+
+/**
+ * Forwards the rows to the frontend in batches of batch_size.
+ */
+pub struct ChannelSink {
+    channel: Channel<QueryStreamEvent>,
+    batch_size: usize,
+    start_time: SystemTime,
+    batch: Vec<Vec<JsonValue>>,
+    batch_started: SystemTime,
+    statement_started: SystemTime,
+    first_batch_ms: Option<u128>,
+    /// the statement being read, counted from 1
+    num_results: usize,
+    /// rows of the current statement
+    num_rows: usize,
+    total_num_rows: usize,
+}
+impl ChannelSink {
+    pub fn new(channel: Channel<QueryStreamEvent>, batch_size: Option<usize>) -> ChannelSink {
+        let batch_size = batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1);
+        ChannelSink {
+            channel: channel,
+            batch_size: batch_size,
+            start_time: SystemTime::now(),
+            batch: Vec::with_capacity(batch_size),
+            batch_started: SystemTime::now(),
+            statement_started: SystemTime::now(),
+            first_batch_ms: None,
+            num_results: 0,
+            num_rows: 0,
+            total_num_rows: 0,
+        }
+    }
+
+    fn elapsed_ms(&self) -> u128 {
+        self.start_time
+            .elapsed()
+            .map(|elapsed| elapsed.as_millis())
+            .unwrap_or_default()
+    }
+
+    fn flush(&mut self) -> Result<(), AppError> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let elapsed_ms = self.elapsed_ms();
+        self.first_batch_ms.get_or_insert(elapsed_ms);
+        let rows = std::mem::replace(&mut self.batch, Vec::with_capacity(self.batch_size));
+        self.batch_started = SystemTime::now();
+        self.channel
+            .send(QueryStreamEvent::Rows {
+                result_index: self.result_index(),
+                rows: rows,
+                num_rows: self.num_rows,
+                elapsed_ms: elapsed_ms,
+            })
+            .map_err(AppError::from)
+    }
+
+    fn result_index(&self) -> usize {
+        self.num_results.saturating_sub(1)
+    }
+
+    /**
+     * Sends whatever is left in the buffer and returns the summary.
+     */
+    pub fn finish(mut self) -> Result<QueryStreamSummary, AppError> {
+        self.flush()?;
+        Ok(QueryStreamSummary {
+            elapsed_ms: self.elapsed_ms().to_string(),
+            first_batch_ms: self.first_batch_ms.map(|ms| ms.to_string()),
+            num_rows: self.total_num_rows.to_string(),
+            num_results: self.num_results,
+        })
+    }
+}
+impl QuerySink for ChannelSink {
+    async fn columns(&mut self, columns: Vec<QueryColumn>) -> Result<(), AppError> {
+        self.num_results += 1;
+        self.num_rows = 0;
+        self.channel
+            .send(QueryStreamEvent::Columns {
+                result_index: self.result_index(),
+                columns: columns,
+            })
+            .map_err(AppError::from)
+    }
+    async fn row(&mut self, row: Vec<JsonValue>) -> Result<(), AppError> {
+        self.batch.push(row);
+        self.num_rows += 1;
+        self.total_num_rows += 1;
+
+        let batch_age = self.batch_started.elapsed().unwrap_or_default();
+        if self.batch.len() >= self.batch_size || batch_age >= MAX_BATCH_DELAY {
+            self.flush()?;
+        }
+        Ok(())
+    }
+    async fn end_statement(&mut self, info: StatementInfo) -> Result<(), AppError> {
+        self.flush()?;
+        let elapsed_ms = self
+            .statement_started
+            .elapsed()
+            .map(|elapsed| elapsed.as_millis())
+            .unwrap_or_default();
+        self.statement_started = SystemTime::now();
+        self.channel
+            .send(QueryStreamEvent::End {
+                result_index: self.result_index(),
+                num_rows: self.num_rows,
+                info: info,
+                elapsed_ms: elapsed_ms,
+            })
+            .map_err(AppError::from)
+    }
+}
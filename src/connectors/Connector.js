@@ -1,4 +1,4 @@
-import { invoke } from '@tauri-apps/api/core';
+import { Channel, invoke } from '@tauri-apps/api/core';
 import QueryResult from '~/services/QueryResult';
 import { defaultColor } from '~/store/main.js';
 
@@ -7,12 +7,17 @@ import { defaultColor } from '~/store/main.js';
  * @property elapsed_ms String,
  * @property num_rows String,
  * @property fields Array<String>,
- * @property rows Array<Object>,
+ * @property rows Array<Array>,
  */
 
 class Connector {
     options = {};
     color = defaultColor;
+    runningQueryIds = new Set();
+    /** set once connected, every adapter_* command addresses the session by it */
+    sessionId = null;
+    /** the open transaction of a pinned session, see begin() */
+    transaction = null;
 
     constructor({ color, ...options }) {
         if (color) {
@@ -37,6 +42,8 @@ class Connector {
         return {
             ...this.options,
             title: this.name,
+            // keeps one connection to the session, so its state carries over from one query to the next
+            pinned: Boolean(this.options.pinned),
             driverOpts: {
                 driver: this.options.driverName,
                 ...this.options.driverOpts,
@@ -47,32 +54,48 @@ class Connector {
                 filepath: this.options.driverOpts?.filepath ?? '',
             },
             sshOpts: !this.options.useSsh ? null : {
-                host: this.options.sshOpts?.host ?? '',
-                port: Number(this.options.sshOpts?.port ?? 22),
-                user: this.options.sshOpts?.user ?? '',
-                password: this.options.sshOpts?.password ?? '',
-                keyfile: this.options.sshOpts?.keyfile ?? undefined,
+                // the jump hosts come first, the host reaching the database last
+                hops: [...(this.options.sshOpts?.proxyJumps ?? []), this.options.sshOpts].map(Connector.sshHop),
+                keepalive_interval: Number(this.options.sshOpts?.keepaliveInterval ?? 30),
             }
         }
     }
 
+    static sshHop(hop) {
+        return {
+            auth: hop?.auth ?? 'Credentials',
+            host: hop?.host ?? '',
+            port: Number(hop?.port ?? 22),
+            user: hop?.user ?? '',
+            password: hop?.password ?? '',
+            keyfile: hop?.keyfile ?? undefined,
+        };
+    }
+
     setDatabase() { throw Error('setDatabase not implemented') }
     getDatabase() { throw Error('getDatabase not implemented') }
     async loadDatabases() { throw Error('loadDatabases not implemented') }
     async loadTables() { throw Error('loadTables not implemented') }
 
     /**
-     * Tells the rust-end to create a connection. Internally, this is bound to the window.
+     * Tells the rust-end to create a connection. Internally, it is a session of the window, which
+     * may hold several (e.g. one per tab).
      */
     async connect() {
-        return await invoke('adapter_connect', structuredClone(this.connectOpts));
+        this.sessionId = await invoke('adapter_connect', structuredClone(this.connectOpts));
+        return this.sessionId;
     }
 
     /**
-     * Tells the rust-end connection to disconnect
+     * Tells the rust-end connection to disconnect.
+     * With a transaction open, the user is asked to roll it back first; if they don't, this
+     * rejects with { kind: 'cancelled' } and the connection stays.
      */
     async disconnect() {
-        return await invoke('adapter_disconnect');
+        const response = await invoke('adapter_disconnect', { sessionId: this.sessionId });
+        this.sessionId = null;
+        this.transaction = null;
+        return response;
     }
 
     /**
@@ -82,6 +105,10 @@ class Connector {
         try {
             await this.disconnect();
         } catch (e) {
+            // the user chose to keep the open transaction
+            if (e?.kind === 'cancelled') {
+                throw e;
+            }
             console.warn(e);
         }
         await this.connect();
@@ -97,16 +124,128 @@ class Connector {
     }
 
     /**
-     * Runs a query
-     * @TODO add "cancel" capability
+     * Runs a query, resolving with one QueryResult per statement.
+     * It can be stopped with cancel(), which rejects it with { kind: 'cancelled' }.
+     * Errors are { kind, error, code, sqlstate, position, retryable }, see src-tauri/src/error.rs
+     * Only the first pageSize rows are read; if more are left, the last result is truncated and
+     * the rest can be read with fetchPage(result.cursorId).
      * @param {String} query
-     * @param {String} database -- optional
+     * @param {Number} pageSize -- optional, 0 reads the whole result
+     * @param {Boolean} background -- optional, runs on the pool even if the session is pinned, e.g. for metadata
+     * @returns {Promise<Array<QueryResult>>}
      */
-    async query(query) {
+    async query(query, { pageSize, background } = {}) {
         const database = this.getDatabase();
-        const response = await invoke('adapter_query', { query, database });
-        console.log('invoke adapter_query', { query, database, response });
-        return new QueryResult(response);
+        const queryId = crypto.randomUUID();
+        this.runningQueryIds.add(queryId);
+        try {
+            const response = await invoke('adapter_query', { sessionId: this.sessionId, query, database, queryId, pageSize, background });
+            console.log('invoke adapter_query', { query, database, response });
+            return response.map(result => new QueryResult(result));
+        } finally {
+            this.runningQueryIds.delete(queryId);
+        }
+    }
+
+    /**
+     * Reads the next page of a truncated result. Merge it with appendPage()
+     * @param {String} cursorId
+     * @returns {Promise<Array<QueryResult>>}
+     */
+    async fetchPage(cursorId) {
+        const response = await invoke('adapter_fetch_page', { sessionId: this.sessionId, cursorId });
+        return response.map(result => new QueryResult(result));
+    }
+
+    /**
+     * Stops reading a truncated result
+     * @param {String} cursorId
+     */
+    async closeCursor(cursorId) {
+        return await invoke('adapter_close_cursor', { sessionId: this.sessionId, cursorId });
+    }
+
+    /**
+     * Runs a query, handing the rows to onRows in batches as the server sends them.
+     * Resolves with { elapsed_ms, first_batch_ms, num_rows, num_results } once every batch arrived.
+     * @param {String} query
+     * @param {Function} onColumns -- called with (columns, resultIndex) as each statement starts
+     * @param {Function} onRows -- called with (rows, { result_index, num_rows, elapsed_ms }) for every batch
+     * @param {Function} onEnd -- called with ({ result_index, num_rows, affected_rows, last_insert_id, warning_count, elapsed_ms }) as each statement ends
+     * @param {Number} batchSize -- optional
+     * @param {Boolean} background -- optional, see query()
+     */
+    async queryStream(query, { onColumns, onRows, onEnd, batchSize, background } = {}) {
+        const database = this.getDatabase();
+        const queryId = crypto.randomUUID();
+        const onEvent = new Channel();
+        onEvent.onmessage = (message) => {
+            if (message.event === 'columns') {
+                onColumns?.(message.columns, message.result_index);
+            } else if (message.event === 'rows') {
+                const { rows, ...progress } = message;
+                onRows?.(rows, progress);
+            } else if (message.event === 'end') {
+                onEnd?.(message);
+            }
+        };
+        this.runningQueryIds.add(queryId);
+        try {
+            return await invoke('adapter_query_stream', {
+                sessionId: this.sessionId, query, database, queryId, batchSize, background, onEvent,
+            });
+        } finally {
+            this.runningQueryIds.delete(queryId);
+        }
+    }
+
+    /**
+     * Starts a transaction spanning the queries to come, until commit() or rollback().
+     * Only pinned connections (the "Dedicated Connection" option) can hold one. It is rolled back
+     * once idle for options.autoRollbackSecs, if set.
+     * Resolves, like every transaction method, with { open, started_at, idle_secs, savepoints, auto_rollback_secs }
+     */
+    async begin() {
+        const autoRollbackSecs = Number(this.options.autoRollbackSecs ?? 0) || null;
+        this.transaction = await invoke('adapter_begin', { sessionId: this.sessionId, autoRollbackSecs });
+        return this.transaction;
+    }
+
+    async commit() {
+        this.transaction = await invoke('adapter_commit', { sessionId: this.sessionId });
+        return this.transaction;
+    }
+
+    /**
+     * @param {String} savepoint -- optional, only rolls back what was done since then
+     */
+    async rollback(savepoint = null) {
+        this.transaction = await invoke('adapter_rollback', { sessionId: this.sessionId, savepoint });
+        return this.transaction;
+    }
+
+    /**
+     * @param {String} name -- a plain identifier, e.g. before_cleanup
+     */
+    async savepoint(name) {
+        this.transaction = await invoke('adapter_savepoint', { sessionId: this.sessionId, name });
+        return this.transaction;
+    }
+
+    /**
+     * Refreshes the state of the transaction, e.g. its idle time
+     */
+    async loadTransaction() {
+        this.transaction = await invoke('adapter_transaction', { sessionId: this.sessionId });
+        return this.transaction;
+    }
+
+    /**
+     * Cancels every query this connector is running
+     */
+    async cancel() {
+        const queryIds = [...this.runningQueryIds];
+        return await Promise.all(queryIds.map(queryId => invoke('adapter_cancel', { sessionId: this.sessionId, queryId })));
     }
 }
 
@@ -9,7 +9,7 @@ class MysqlConnector extends Connector {
     }
 
     async loadDatabases() {
-        const result = await this.query('SHOW DATABASES;');
+        const [result] = await this.query('SHOW DATABASES;', { background: true });
         console.log('loadSchemas', result);
         return result?.rows?.map(row => Object.values(row)[0]);
     }
@@ -17,7 +17,7 @@ class MysqlConnector extends Connector {
     async loadTables() {
         const schema = this.getDatabase();
         if (schema) {
-            const result = await this.query(`SHOW TABLES IN ${schema};`);
+            const [result] = await this.query(`SHOW TABLES IN ${schema};`, { background: true });
             console.log('loadTables', result);
             return result?.rows?.map(row => Object.values(row)[0]);
         }
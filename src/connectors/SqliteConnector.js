@@ -10,7 +10,7 @@ class SqliteConnector extends Connector {
     
     async loadDatabases() {
         try {
-            const { rows } = await this.query('PRAGMA database_list');
+            const [{ rows }] = await this.query('PRAGMA database_list', { background: true });
             return rows?.map(row => Object.values(row)[1] ?? null).filter(v => v);
 
         } catch (error) {
@@ -31,7 +31,7 @@ class SqliteConnector extends Connector {
         try {
             const { rows } = await Promise.any(
                 tables.map(async table => {
-                    const rsp = await this.query(`SELECT name FROM ${table} WHERE type IN("table", "view") AND name NOT LIKE "sqlite_%"`);
+                    const [rsp] = await this.query(`SELECT name FROM ${table} WHERE type IN("table", "view") AND name NOT LIKE "sqlite_%"`, { background: true });
                     if (!rsp?.fields?.length) {
                         throw new Error(`${table} does not exist`);
                     }
@@ -76,13 +76,13 @@ class TestConnector extends Connector {
             secondary_contact_birthdate: faker.date.birthdate().toJSON(),
         }));
         const queryResult = new QueryResult({
-            rows,
-            fields: Object.keys(rows[0]),
+            rows: rows.map(row => Object.values(row)),
+            fields: Object.keys(rows[0] ?? {}),
             num_rows,
             elapsed_ms,
         });
         console.log('QueryResult:', queryResult);
-        return queryResult;
+        return [queryResult];
     }
 }
 export { TestConnector };
\ No newline at end of file
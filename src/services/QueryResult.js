@@ -1,23 +1,71 @@
-import { toNumber } from '~/helpers.js';
+import { isNull, toNumber } from '~/helpers.js';
 
 /**
+ * The result of one statement of a query.
  * @typedef QueryResult
+ * @property resultIndex Number -- the position of the statement in the script
  * @property elapsedMs Number,
  * @property numRows Number,
  * @property fields Array<String>,
- * @property rows Array<Object>,
+ * @property columns Array<Object> -- { name, type_name, nullable, length, precision, scale, primary_key, auto_increment, table }
+ * @property rows Array<Array> -- positional; row[i] is the value of fields[i]
+ * @property affectedRows Number|null
+ * @property lastInsertId String|null
+ * @property warningCount Number|null
+ * @property warnings Array<Object> -- { level, code, message }
+ * @property truncated Boolean -- true while more rows can be fetched through cursorId
+ * @property totalRows Number|null -- the size of the whole result, if known
+ * @property cursorId String|null
  */
 class QueryResult {
+    resultIndex = 0;
     numRows = null;
     elapsedMs = null;
     rows = null;
     fields = null;
+    columns = null;
+    affectedRows = null;
+    lastInsertId = null;
+    warningCount = null;
+    warnings = [];
+    truncated = false;
+    totalRows = null;
+    cursorId = null;
 
-    constructor({ rows, fields, num_rows, elapsed_ms }) {
+    constructor({
+        result_index, rows, fields, columns, num_rows, elapsed_ms,
+        affected_rows, last_insert_id, warning_count, warnings, truncated, total_rows, cursor_id,
+    }) {
+        this.resultIndex = result_index ?? 0;
         this.elapsedMs = toNumber(elapsed_ms);
         this.numRows = toNumber(num_rows);
         this.fields = fields ?? [];
+        this.columns = columns ?? this.fields.map(name => ({ name }));
         this.rows = rows ?? [];
+        this.affectedRows = affected_rows == null ? null : toNumber(affected_rows);
+        this.lastInsertId = last_insert_id ?? null;
+        this.warningCount = warning_count == null ? null : toNumber(warning_count);
+        this.warnings = warnings ?? [];
+        this.truncated = truncated ?? false;
+        this.totalRows = total_rows == null ? null : toNumber(total_rows);
+        this.cursorId = cursor_id ?? null;
+    }
+
+    /**
+     * Appends the next page of the same result
+     * @param {QueryResult} page
+     */
+    append(page) {
+        this.rows = this.rows.concat(page.rows);
+        this.numRows = this.rows.length;
+        this.elapsedMs += page.elapsedMs;
+        this.affectedRows = page.affectedRows;
+        this.lastInsertId = page.lastInsertId;
+        this.warningCount = page.warningCount;
+        this.warnings = page.warnings;
+        this.truncated = page.truncated;
+        this.totalRows = page.totalRows;
+        this.cursorId = page.cursorId;
     }
 
     get num_rows() {
@@ -27,6 +75,32 @@ class QueryResult {
     get elapsed_ms() {
         return this.elapsedMs;
     }
+
+    /**
+     * True for statements that don't return rows, e.g. INSERT or UPDATE
+     */
+    get isWrite() {
+        return !this.columns.length && !isNull(this.affectedRows);
+    }
+}
+
+/**
+ * Adds the results of the next page to those of the previous pages. The first result of a page
+ * may continue the last statement of the previous one.
+ * @param {Array<QueryResult>} results
+ * @param {Array<QueryResult>} page
+ */
+function appendPage(results, page) {
+    for (const result of page) {
+        const last = results[results.length - 1];
+        if (last?.resultIndex === result.resultIndex) {
+            last.append(result);
+        } else {
+            results.push(result);
+        }
+    }
+    return results;
 }
 
+export { appendPage };
 export default QueryResult;
\ No newline at end of file
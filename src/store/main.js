@@ -19,11 +19,14 @@ export default defineStore('favorites', () => {
             canSsh: true,
             useSsh: false,
             sshOpts: {
+                auth: 'Credentials',
                 user: '',
                 host: '',
                 port: 22,
                 password: '',
                 keyfile: null,
+                proxyJumps: [],
+                keepaliveInterval: 30,
             },
             driverName: null,
             driverOpts: {},